@@ -0,0 +1,25 @@
+use eye_config::blocking::BlockingPersistableState;
+use eye_config::cli::global_args::GlobalArgs;
+use eye_config::cli::init_tracing::init_tracing;
+use eye_config::persistable_state::PersistableState;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, PersistableState)]
+#[eye_config(project = "eye_config_examples", file = "example-derived_config.toml")]
+pub struct LaunchCountConfig {
+    pub launches: u64,
+}
+
+fn main() -> eyre::Result<()> {
+    color_eyre::install()?;
+    init_tracing(&GlobalArgs::default(), std::io::stderr)?;
+
+    info!("Run the program multiple times to see the launch count go up.");
+
+    let config = LaunchCountConfig::update_blocking(|config| config.launches += 1)?;
+    info!("This example has been launched {} times.", config.launches);
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Derive macros for `eye_config`, enable its `derive` feature to use them.
+
+mod persistable_state;
+mod redacted_debug;
+
+use proc_macro::TokenStream;
+use syn::DeriveInput;
+use syn::parse_macro_input;
+
+/// Implement `PersistableState` for a type with a constant key.
+///
+/// ```ignore
+/// #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default, PersistableState)]
+/// #[eye_config(project = "my_project", file = "settings.toml")]
+/// struct Settings {
+///     theme: String,
+/// }
+/// ```
+///
+/// Attributes:
+/// - `project = "..."`, required, the project name of the key.
+/// - `file = "..."`, required, the file slug of the key.
+/// - `secret`, exclude the config from the registry of known projects.
+/// - `format = "..."`, one of `json`, `json5`, `toml`, `yaml` or `ron`,
+///   by default the format is picked from the extension of the file slug.
+/// - `kind = "..."`, one of `config`, `data`, `cache`, `state` or `runtime`,
+///   the directory the file is stored in, by default `config`.
+/// - `schema`, expose the schema of a type deriving `JsonSchema`, requires the `schemars` feature.
+///   `schema = "sidecar"` also writes it next to the config and `schema = "<url>"` references
+///   a published copy, see `SchemaReference`.
+#[proc_macro_derive(PersistableState, attributes(eye_config))]
+pub fn derive_persistable_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match persistable_state::expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+/// Implement `Debug`, printing fields marked `#[secret]` as `[redacted]`.
+///
+/// ```ignore
+/// #[derive(RedactedDebug)]
+/// struct Credentials {
+///     user: String,
+///     #[secret]
+///     token: String,
+/// }
+/// ```
+#[proc_macro_derive(RedactedDebug, attributes(secret))]
+pub fn derive_redacted_debug(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match redacted_debug::expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
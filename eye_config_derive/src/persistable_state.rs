@@ -0,0 +1,212 @@
+use proc_macro2::Span;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+use syn::Ident;
+use syn::LitStr;
+
+struct Options {
+    project: LitStr,
+    file: LitStr,
+    secret: bool,
+    format: Option<Ident>,
+    kind: Option<Ident>,
+    /// `Some(None)` for a bare `schema`, or the reference given as `schema = "..."`.
+    schema: Option<Option<LitStr>>,
+}
+
+fn parse_options(input: &DeriveInput) -> syn::Result<Options> {
+    let mut project: Option<LitStr> = None;
+    let mut file: Option<LitStr> = None;
+    let mut secret = false;
+    let mut format = None;
+    let mut kind = None;
+    let mut schema: Option<Option<LitStr>> = None;
+    let mut found = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("eye_config"))
+    {
+        found = true;
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("project") {
+                set_once(&mut project, meta.value()?.parse()?, &meta.path, "project")
+            } else if meta.path.is_ident("file") {
+                set_once(&mut file, meta.value()?.parse()?, &meta.path, "file")
+            } else if meta.path.is_ident("secret") {
+                if secret {
+                    return Err(meta.error("duplicate `secret` attribute"));
+                }
+                secret = true;
+                Ok(())
+            } else if meta.path.is_ident("format") {
+                let value: LitStr = meta.value()?.parse()?;
+                let variant = format_variant(&value)?;
+                set_once(&mut format, variant, &meta.path, "format")
+            } else if meta.path.is_ident("kind") {
+                let value: LitStr = meta.value()?.parse()?;
+                let variant = kind_variant(&value)?;
+                set_once(&mut kind, variant, &meta.path, "kind")
+            } else if meta.path.is_ident("schema") {
+                let reference: Option<LitStr> = match meta.input.peek(syn::Token![=]) {
+                    true => Some(meta.value()?.parse()?),
+                    false => None,
+                };
+                set_once(&mut schema, reference, &meta.path, "schema")
+            } else {
+                Err(meta.error(
+                    "unknown eye_config attribute, expected `project`, `file`, `secret`, `format`, `kind` or `schema`",
+                ))
+            }
+        })?;
+    }
+    if !found {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "deriving PersistableState requires `#[eye_config(project = \"...\", file = \"...\")]`",
+        ));
+    }
+    let missing = |name: &str| {
+        syn::Error::new_spanned(
+            &input.ident,
+            format!("missing `{name} = \"...\"` in `#[eye_config(...)]`"),
+        )
+    };
+    let project = project.ok_or_else(|| missing("project"))?;
+    let file = file.ok_or_else(|| missing("file"))?;
+    for (value, name) in [(&project, "project"), (&file, "file")] {
+        if value.value().is_empty() {
+            return Err(syn::Error::new_spanned(
+                value,
+                format!("`{name}` must not be empty"),
+            ));
+        }
+    }
+    Ok(Options {
+        project,
+        file,
+        secret,
+        format,
+        kind,
+        schema,
+    })
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T, path: &syn::Path, name: &str) -> syn::Result<()> {
+    if slot.is_some() {
+        return Err(syn::Error::new_spanned(
+            path,
+            format!("duplicate `{name}` attribute"),
+        ));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+fn format_variant(value: &LitStr) -> syn::Result<Ident> {
+    let variant = match value.value().to_ascii_lowercase().as_str() {
+        "json" => "Json",
+        "json5" => "Json5",
+        "toml" => "Toml",
+        "yaml" | "yml" => "Yaml",
+        "ron" => "Ron",
+        _ => {
+            return Err(syn::Error::new_spanned(
+                value,
+                "unknown format, expected one of `json`, `json5`, `toml`, `yaml` or `ron`",
+            ));
+        }
+    };
+    Ok(Ident::new(variant, Span::call_site()))
+}
+
+fn kind_variant(value: &LitStr) -> syn::Result<Ident> {
+    let variant = match value.value().to_ascii_lowercase().as_str() {
+        "config" => "Config",
+        "data" => "Data",
+        "cache" => "Cache",
+        "state" => "State",
+        "runtime" => "Runtime",
+        _ => {
+            return Err(syn::Error::new_spanned(
+                value,
+                "unknown kind, expected one of `config`, `data`, `cache`, `state` or `runtime`",
+            ));
+        }
+    };
+    Ok(Ident::new(variant, Span::call_site()))
+}
+
+pub fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let Options {
+        project,
+        file,
+        secret,
+        format,
+        kind,
+        schema,
+    } = parse_options(input)?;
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let format = format.map(|variant| {
+        quote! {
+            fn format(
+                _key: &::eye_config::persistence_key::PersistenceKey,
+            ) -> ::eye_config::format::Format {
+                ::eye_config::format::Format::#variant
+            }
+        }
+    });
+    let kind = kind.map(|variant| {
+        quote! {
+            .with_kind(::eye_config::persistence_key::DirectoryKind::#variant)
+        }
+    });
+    let secret = secret.then(|| {
+        quote! {
+            fn is_secret() -> bool {
+                true
+            }
+        }
+    });
+
+    let schema = schema.map(|reference| {
+        let reference = reference.map(|reference| {
+            let variant = match reference.value().as_str() {
+                "sidecar" => quote!(::eye_config::schema::SchemaReference::Sidecar),
+                _ => quote!(::eye_config::schema::SchemaReference::Url(#reference.to_string())),
+            };
+            quote! {
+                fn schema_reference() -> ::eye_config::schema::SchemaReference {
+                    #variant
+                }
+            }
+        });
+        quote! {
+            fn schema() -> ::core::option::Option<::eye_config::schemars::Schema> {
+                ::core::option::Option::Some(::eye_config::schemars::schema_for!(Self))
+            }
+
+            #reference
+        }
+    });
+
+    Ok(quote! {
+        #[::eye_config::async_trait::async_trait]
+        impl #impl_generics ::eye_config::persistable_state::PersistableState for #ident #type_generics #where_clause {
+            async fn key() -> ::eye_config::eyre::Result<::eye_config::persistence_key::PersistenceKey> {
+                <Self as ::eye_config::persistable_state::PersistableState>::key_blocking()
+            }
+
+            fn key_blocking() -> ::eye_config::eyre::Result<::eye_config::persistence_key::PersistenceKey> {
+                ::core::result::Result::Ok(::eye_config::persistence_key::PersistenceKey::new(#project, #file)#kind)
+            }
+
+            #format
+            #secret
+            #schema
+        }
+    })
+}
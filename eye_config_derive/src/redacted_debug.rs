@@ -0,0 +1,117 @@
+use proc_macro2::TokenStream;
+use quote::format_ident;
+use quote::quote;
+use syn::Data;
+use syn::DeriveInput;
+use syn::Field;
+use syn::Fields;
+use syn::parse_quote;
+
+pub fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let arms = match &input.data {
+        Data::Struct(data) => vec![arm(quote!(Self), &name, &data.fields)?],
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .map(|variant| {
+                let variant_ident = &variant.ident;
+                arm(
+                    quote!(Self::#variant_ident),
+                    &variant_ident.to_string(),
+                    &variant.fields,
+                )
+            })
+            .collect::<syn::Result<Vec<_>>>()?,
+        Data::Union(data) => {
+            return Err(syn::Error::new_spanned(
+                data.union_token,
+                "RedactedDebug cannot be derived for unions",
+            ));
+        }
+    };
+
+    let mut generics = input.generics.clone();
+    for param in input.generics.type_params() {
+        let param = &param.ident;
+        generics
+            .make_where_clause()
+            .predicates
+            .push(parse_quote!(#param: ::core::fmt::Debug));
+    }
+    let (impl_generics, type_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::core::fmt::Debug for #ident #type_generics #where_clause {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// A match arm formatting one struct or enum variant.
+fn arm(path: TokenStream, name: &str, fields: &Fields) -> syn::Result<TokenStream> {
+    match fields {
+        Fields::Named(fields) => {
+            let mut patterns = Vec::new();
+            let mut calls = Vec::new();
+            for field in &fields.named {
+                let field_ident = field.ident.as_ref().expect("named fields have idents");
+                let field_name = field_ident.to_string();
+                if is_secret(field)? {
+                    patterns.push(quote!(#field_ident: _));
+                    calls.push(quote!(.field(#field_name, &::core::format_args!("[redacted]"))));
+                } else {
+                    patterns.push(quote!(#field_ident));
+                    calls.push(quote!(.field(#field_name, #field_ident)));
+                }
+            }
+            Ok(quote! {
+                #path { #(#patterns),* } => f.debug_struct(#name) #(#calls)* .finish(),
+            })
+        }
+        Fields::Unnamed(fields) => {
+            let mut patterns = Vec::new();
+            let mut calls = Vec::new();
+            for (index, field) in fields.unnamed.iter().enumerate() {
+                if is_secret(field)? {
+                    patterns.push(quote!(_));
+                    calls.push(quote!(.field(&::core::format_args!("[redacted]"))));
+                } else {
+                    let binding = format_ident!("field_{index}");
+                    patterns.push(quote!(#binding));
+                    calls.push(quote!(.field(#binding)));
+                }
+            }
+            Ok(quote! {
+                #path ( #(#patterns),* ) => f.debug_tuple(#name) #(#calls)* .finish(),
+            })
+        }
+        Fields::Unit => Ok(quote! {
+            #path => f.write_str(#name),
+        }),
+    }
+}
+
+fn is_secret(field: &Field) -> syn::Result<bool> {
+    let mut secret = false;
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("secret"))
+    {
+        attr.meta.require_path_only()?;
+        if secret {
+            return Err(syn::Error::new_spanned(
+                attr,
+                "duplicate `secret` attribute",
+            ));
+        }
+        secret = true;
+    }
+    Ok(secret)
+}
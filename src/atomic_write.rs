@@ -0,0 +1,226 @@
+use eyre::Context;
+use eyre::OptionExt;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Replace the file at `path` with `contents` so that readers only ever observe the old or the new contents.
+///
+/// The contents are written and fsynced to a sibling temp file which is then renamed over the target,
+/// after which the parent directory is fsynced so the rename itself survives a crash.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> eyre::Result<()> {
+    write_atomic_with_mode(path, contents, None)
+}
+
+/// Like [`write_atomic`], giving the file the Unix permission bits `mode` instead of relying on the umask.
+/// The temp file is created with the mode, so the contents are never readable with wider permissions.
+/// Without a mode, an existing file keeps its permissions. The mode is ignored on other platforms.
+///
+/// If `path` is a symlink, the file it points to is replaced and the link is kept.
+pub fn write_atomic_with_mode(
+    path: &Path,
+    contents: impl AsRef<[u8]>,
+    mode: Option<u32>,
+) -> eyre::Result<()> {
+    write_atomic_with(path, mode, |file| file.write_all(contents.as_ref()))
+}
+
+fn write_atomic_with(
+    path: &Path,
+    mode: Option<u32>,
+    write: impl FnOnce(&mut fs::File) -> std::io::Result<()>,
+) -> eyre::Result<()> {
+    let target = resolve_symlinks(path)
+        .wrap_err_with(|| format!("Failed to resolve symlinks of {}", path.display()))?;
+    let dir = target
+        .parent()
+        .ok_or_eyre("Cannot atomically write a path without a parent directory")?;
+    let temp_path = temp_path_for(&target)?;
+    let result = (|| {
+        let mode = match mode {
+            Some(mode) => Some(mode),
+            None => existing_mode(&target)?,
+        };
+        let mut file = create_temp_file(&temp_path, mode)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&temp_path, &target)?;
+        sync_dir(dir)?;
+        std::io::Result::Ok(())
+    })();
+    if result.is_err() {
+        // Best effort, the target file has not been touched at this point.
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.wrap_err_with(|| format!("Failed to atomically write {}", path.display()))
+}
+
+/// Follow symlinks to the file they point to, which may not exist yet.
+/// Renaming over the link itself would replace it with a regular file.
+fn resolve_symlinks(path: &Path) -> std::io::Result<PathBuf> {
+    // The same limit as Linux, to stop on symlink loops.
+    const MAX_LINKS: usize = 40;
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link = fs::read_link(&path)?;
+                // Relative links are relative to the directory containing the link.
+                path = match path.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                };
+            }
+            Ok(_) => return Ok(path),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(path),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(std::io::Error::other(format!(
+        "Too many levels of symlinks at {}",
+        path.display()
+    )))
+}
+
+/// The temp file lives in the same directory as the target so the rename never crosses filesystems.
+fn temp_path_for(path: &Path) -> eyre::Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_eyre("Cannot atomically write a path without a file name")?;
+    let unique = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    Ok(path.with_file_name(format!(
+        ".{}.{}.{unique}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    )))
+}
+
+#[cfg(unix)]
+fn create_temp_file(path: &Path, mode: Option<u32>) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::fs::PermissionsExt;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    let Some(mode) = mode else {
+        return options.open(path);
+    };
+    let file = options.mode(mode).open(path)?;
+    // The umask may have removed bits from the requested mode.
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+    Ok(file)
+}
+
+/// The permission bits of the file being replaced, so a save does not reset them to the umask default.
+#[cfg(unix)]
+fn existing_mode(path: &Path) -> std::io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    match fs::metadata(path) {
+        Ok(metadata) => Ok(Some(metadata.permissions().mode() & 0o7777)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn existing_mode(path: &Path) -> std::io::Result<Option<u32>> {
+    let _ = path;
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+fn create_temp_file(path: &Path, mode: Option<u32>) -> std::io::Result<fs::File> {
+    let _ = mode;
+    fs::File::create(path)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    // Directories cannot be opened for syncing on Windows, the rename is flushed with the file system metadata.
+    let _ = dir;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir_entries(dir: &Path) -> Vec<String> {
+        let mut names = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn interrupted_write_keeps_previous_contents() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("settings.json");
+        write_atomic(&path, "old")?;
+
+        let result = write_atomic_with(&path, None, |file| {
+            file.write_all(b"half of the new")?;
+            Err(std::io::Error::other("interrupted"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path)?, "old");
+        assert_eq!(dir_entries(dir.path()), ["settings.json"]);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_write_into_missing_dir_leaves_nothing() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("missing").join("settings.json");
+
+        assert!(write_atomic(&path, "new").is_err());
+        assert!(dir_entries(dir.path()).is_empty());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_through_symlink_keeps_link() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = dir.path().join("dotfiles-settings.json");
+        let link = dir.path().join("settings.json");
+        fs::write(&target, "old")?;
+        std::os::unix::fs::symlink("dotfiles-settings.json", &link)?;
+
+        write_atomic(&link, "new")?;
+
+        assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target)?, "new");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_keeps_existing_permissions() -> eyre::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("settings.json");
+        fs::write(&path, "old")?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+        write_atomic(&path, "new")?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o600);
+
+        write_atomic_with_mode(&path, "newer", Some(0o640))?;
+        assert_eq!(fs::metadata(&path)?.permissions().mode() & 0o777, 0o640);
+        Ok(())
+    }
+}
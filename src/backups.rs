@@ -0,0 +1,239 @@
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Timelike;
+use chrono::Utc;
+use eyre::OptionExt;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+use tracing::debug;
+
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Limits on the `.bak` files kept next to a config, enforced whenever a backup is written.
+///
+/// A backup is removed when it breaks any of the limits, except the newest backup which is always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BackupRetention {
+    /// Keep at most this many backups.
+    pub keep_last: Option<usize>,
+    /// Remove backups older than this.
+    pub max_age: Option<Duration>,
+    /// Keep the newest backups whose combined size fits in this many bytes.
+    pub max_total_bytes: Option<u64>,
+}
+
+impl BackupRetention {
+    /// Keep every backup forever.
+    pub const UNLIMITED: Self = Self {
+        keep_last: None,
+        max_age: None,
+        max_total_bytes: None,
+    };
+}
+
+static DEFAULT_RETENTION: RwLock<BackupRetention> = RwLock::new(BackupRetention::UNLIMITED);
+
+/// The retention used by types that do not override `PersistableState::backup_retention`.
+pub fn default_backup_retention() -> BackupRetention {
+    *DEFAULT_RETENTION
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Change the retention used by types that do not override `PersistableState::backup_retention`.
+/// Backups are kept forever unless this is called.
+pub fn set_default_backup_retention(retention: BackupRetention) {
+    *DEFAULT_RETENTION
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = retention;
+}
+
+/// A `<file name>.<timestamp>.bak` file next to a config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub timestamp: DateTime<Utc>,
+    /// Orders backups made within the same second, written as `<timestamp>-<sequence>`.
+    pub sequence: u32,
+    pub len: u64,
+}
+
+/// The backups of the config file at `path`, newest first.
+///
+/// Older versions named backups `<file stem>.<timestamp>.bak`, which are included unless another
+/// config in the directory shares the stem and they could belong to either.
+pub fn list_backups(path: &Path) -> eyre::Result<Vec<Backup>> {
+    let Some(dir) = path.parent() else {
+        return Ok(Vec::new());
+    };
+    if !fs::exists(dir)? {
+        return Ok(Vec::new());
+    }
+    let file_name = path
+        .file_name()
+        .ok_or_eyre("Config path has no file name")?
+        .to_string_lossy()
+        .into_owned();
+    let stem = path
+        .file_stem()
+        .ok_or_eyre("Config path has no file name")?
+        .to_string_lossy()
+        .into_owned();
+    let entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+    let stem_shared = entries.iter().any(|entry| {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        name != file_name
+            && !name.starts_with('.')
+            && !name.ends_with(".bak")
+            && Path::new(&*name).file_stem() == Some(stem.as_ref())
+    });
+    let prefix = format!("{file_name}.");
+    let legacy_prefix = (stem != file_name && !stem_shared).then(|| format!("{stem}."));
+    let mut backups = Vec::new();
+    for entry in entries {
+        let name = entry.file_name();
+        let Some(rest) = name.to_str().and_then(|name| name.strip_suffix(".bak")) else {
+            continue;
+        };
+        let parsed = rest
+            .strip_prefix(&prefix)
+            .and_then(parse_timestamp)
+            .or_else(|| {
+                legacy_prefix
+                    .as_deref()
+                    .and_then(|legacy_prefix| rest.strip_prefix(legacy_prefix))
+                    .and_then(parse_timestamp)
+            });
+        let Some((timestamp, sequence)) = parsed else {
+            continue;
+        };
+        backups.push(Backup {
+            path: entry.path(),
+            timestamp,
+            sequence,
+            len: entry.metadata()?.len(),
+        });
+    }
+    backups.sort_by_key(|backup| Reverse((backup.timestamp, backup.sequence)));
+    Ok(backups)
+}
+
+/// Copy the config next to itself with a timestamped `.bak` extension, then apply the retention.
+pub fn write_backup(path: &Path, retention: BackupRetention) -> eyre::Result<PathBuf> {
+    let now = Utc::now().with_nanosecond(0).unwrap_or_else(Utc::now);
+    let timestamp = now.format(TIMESTAMP_FORMAT);
+    // Several backups within the same second get a sequence number after the timestamp.
+    let sequence = list_backups(path)?
+        .iter()
+        .filter(|backup| backup.timestamp == now)
+        .map(|backup| backup.sequence + 1)
+        .max();
+    let file_name = path
+        .file_name()
+        .ok_or_eyre("Config path has no file name")?
+        .to_string_lossy();
+    // The whole file name is kept, so configs differing only in extension have separate backups.
+    let backup_path = match sequence {
+        None => path.with_file_name(format!("{file_name}.{timestamp}.bak")),
+        Some(sequence) => path.with_file_name(format!("{file_name}.{timestamp}-{sequence}.bak")),
+    };
+    fs::copy(path, &backup_path)?;
+    enforce_retention(path, retention)?;
+    Ok(backup_path)
+}
+
+/// Remove the backups breaking the retention limits, returning the ones removed.
+pub fn enforce_retention(path: &Path, retention: BackupRetention) -> eyre::Result<Vec<Backup>> {
+    if retention == BackupRetention::UNLIMITED {
+        return Ok(Vec::new());
+    }
+    let now = Utc::now();
+    let mut kept = 0;
+    let mut total_bytes = 0;
+    let mut removed = Vec::new();
+    for backup in list_backups(path)? {
+        total_bytes += backup.len;
+        if kept == 0 {
+            kept += 1;
+            continue;
+        }
+        let too_many = retention.keep_last.is_some_and(|keep| kept >= keep);
+        let too_old = retention.max_age.is_some_and(|max_age| {
+            (now - backup.timestamp)
+                .to_std()
+                .is_ok_and(|age| age > max_age)
+        });
+        let too_big = retention
+            .max_total_bytes
+            .is_some_and(|max| total_bytes > max);
+        if too_many || too_old || too_big {
+            debug!("Removing old backup {}", backup.path.display());
+            fs::remove_file(&backup.path)?;
+            total_bytes -= backup.len;
+            removed.push(backup);
+        } else {
+            kept += 1;
+        }
+    }
+    Ok(removed)
+}
+
+fn parse_timestamp(rest: &str) -> Option<(DateTime<Utc>, u32)> {
+    let (timestamp, sequence) = match rest.split_once('-') {
+        Some((timestamp, sequence)) => (timestamp, sequence.parse().ok()?),
+        None => (rest, 0),
+    };
+    let timestamp = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT).ok()?;
+    Some((timestamp.and_utc(), sequence))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_sharing_a_stem_have_separate_backups() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let json = dir.path().join("settings.json");
+        let toml = dir.path().join("settings.toml");
+        fs::write(&json, "{}")?;
+        fs::write(&toml, "")?;
+
+        let json_backup = write_backup(&json, BackupRetention::UNLIMITED)?;
+        let toml_backup = write_backup(&toml, BackupRetention::UNLIMITED)?;
+
+        let listed = |path: &Path| -> eyre::Result<Vec<PathBuf>> {
+            Ok(list_backups(path)?
+                .into_iter()
+                .map(|backup| backup.path)
+                .collect())
+        };
+        assert_eq!(listed(&json)?, [json_backup]);
+        assert_eq!(listed(&toml)?, [toml_backup]);
+        Ok(())
+    }
+
+    #[test]
+    fn lists_backups_named_by_older_versions() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let json = dir.path().join("settings.json");
+        fs::write(&json, "{}")?;
+        let legacy = dir.path().join("settings.20250101T000000Z.bak");
+        fs::write(&legacy, "{}")?;
+
+        let backups = list_backups(&json)?;
+        assert_eq!(backups.len(), 1);
+        assert_eq!(backups[0].path, legacy);
+
+        // Once another config shares the stem, the old backups could belong to either.
+        fs::write(dir.path().join("settings.toml"), "")?;
+        assert!(list_backups(&json)?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+use crate::cli::config::known_projects::KnownProjects;
+use crate::persistable_state::PersistableState;
+use crate::persistable_state::load_state;
+use crate::persistable_state::reset_state;
+use crate::persistable_state::save_state;
+use crate::persistable_state::update_state;
+use crate::recovery::Loaded;
+use eyre::Result;
+
+/// The [`PersistableState`] API for synchronous code, which does not need a tokio runtime.
+///
+/// Implemented for every [`PersistableState`], sharing its storage, recovery and registry tracking.
+/// The key comes from [`PersistableState::key_blocking`].
+pub trait BlockingPersistableState: PersistableState {
+    /// Load the configuration, see [`PersistableState::load`].
+    fn load_blocking() -> Result<Self> {
+        Ok(Self::load_with_report_blocking()?.state)
+    }
+
+    /// Load the configuration, reporting which recovery ran if the file was invalid.
+    fn load_with_report_blocking() -> Result<Loaded<Self>> {
+        let key = Self::key_blocking()?;
+        let loaded = load_state::<Self>(&*Self::storage(), &key)?;
+
+        if !Self::is_secret() {
+            let format = Self::format(&key);
+            KnownProjects::track_project_accessed_blocking(key, format)?;
+        }
+
+        Ok(loaded)
+    }
+
+    /// Save the configuration, see [`PersistableState::save`].
+    fn save_blocking(&self) -> Result<()> {
+        save_state(&*Self::storage(), &Self::key_blocking()?, self)?;
+        Ok(())
+    }
+
+    /// Load, modify and save the configuration while holding an exclusive lock,
+    /// see [`PersistableState::update`].
+    fn update_blocking<F>(f: F) -> Result<Self>
+    where
+        F: FnOnce(&mut Self),
+    {
+        let key = Self::key_blocking()?;
+        let instance = update_state(&*Self::storage(), &key, f)?;
+
+        if !Self::is_secret() {
+            let format = Self::format(&key);
+            KnownProjects::track_project_accessed_blocking(key, format)?;
+        }
+
+        Ok(instance)
+    }
+
+    /// Back up the stored config and replace it with defaults, see [`PersistableState::reset`].
+    fn reset_blocking() -> Result<Self> {
+        reset_state(&*Self::storage(), &Self::key_blocking()?)
+    }
+
+    /// Modify and save the configuration, see [`PersistableState::modify_and_save`].
+    fn modify_and_save_blocking<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self),
+    {
+        self.try_modify_and_save_blocking(|state| {
+            f(state);
+            Ok(())
+        })
+    }
+
+    /// Modify and save the configuration with a change which can fail,
+    /// see [`PersistableState::try_modify_and_save`].
+    fn try_modify_and_save_blocking<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        let mut modified = self.clone();
+        f(&mut modified)?;
+        let merged = save_state(&*Self::storage(), &Self::key_blocking()?, &modified)?;
+        *self = merged.unwrap_or(modified);
+        Ok(())
+    }
+}
+
+impl<T: PersistableState> BlockingPersistableState for T {}
@@ -0,0 +1,159 @@
+use crate::conflict::last_observed;
+use crate::env_overrides::apply_env_overrides;
+use crate::persistable_state::PersistableState;
+use crate::persistence_key::PersistenceKey;
+use serde::Serialize;
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// How many events a subscriber can fall behind before it misses some.
+const CAPACITY: usize = 64;
+
+/// Identifies the channel of a config by its type and storage location.
+type ChannelId = (TypeId, PathBuf);
+
+/// The channels, created by the first subscriber to each config.
+static CHANNELS: LazyLock<Mutex<HashMap<ChannelId, Box<dyn Any + Send>>>> =
+    LazyLock::new(Default::default);
+
+/// What caused a config to change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOrigin {
+    /// This process saved the config.
+    LocalSave,
+    /// The stored config was changed by another process or by hand, noticed when loading or watching.
+    ExternalChange,
+    /// The config was reset to defaults, by [`PersistableState::reset`] or when recovering from an invalid file.
+    Reset,
+}
+
+/// A change to a config, published to every [`ChangeSubscription`] for it.
+#[derive(Debug, Clone)]
+pub struct ChangeEvent<T> {
+    /// The value before the change, as last seen by this process or the default if it has not seen one.
+    pub old: T,
+    pub new: T,
+    pub origin: ChangeOrigin,
+}
+
+impl<T: Serialize> ChangeEvent<T> {
+    /// Whether the value at a JSON pointer such as `/server/port` differs, the empty pointer is the whole config.
+    pub fn changed(&self, pointer: &str) -> bool {
+        let old = serde_json::to_value(&self.old).unwrap_or_default();
+        let new = serde_json::to_value(&self.new).unwrap_or_default();
+        old.pointer(pointer) != new.pointer(pointer)
+    }
+}
+
+/// Receives the changes made to a config within this process, see [`PersistableState::subscribe`].
+pub struct ChangeSubscription<T> {
+    receiver: broadcast::Receiver<ChangeEvent<T>>,
+    pointers: Vec<String>,
+}
+
+impl<T: PersistableState> ChangeSubscription<T> {
+    /// Only receive changes to the value at a JSON pointer such as `/server/port`, or any of the
+    /// values given in previous calls.
+    pub fn filter(mut self, pointer: impl Into<String>) -> Self {
+        self.pointers.push(pointer.into());
+        self
+    }
+
+    /// Wait for the next change matching the filters.
+    pub async fn recv(&mut self) -> Option<ChangeEvent<T>> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => {
+                    if self.pointers.is_empty()
+                        || self.pointers.iter().any(|pointer| event.changed(pointer))
+                    {
+                        return Some(event);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Missed {missed} changes to a config, the subscriber is too slow");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+struct Channel<T> {
+    sender: broadcast::Sender<ChangeEvent<T>>,
+    /// The value last published, or seen when subscribing.
+    last: Option<T>,
+}
+
+pub(crate) fn subscribe<T: PersistableState>(
+    key: &PersistenceKey,
+    location: &Path,
+) -> eyre::Result<ChangeSubscription<T>> {
+    let mut channels = CHANNELS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let id = (TypeId::of::<T>(), location.to_path_buf());
+    // A channel without receivers may have missed changes, so it is replaced.
+    if let Some(channel) = channels
+        .get(&id)
+        .and_then(|channel| channel.downcast_ref::<Channel<T>>())
+        .filter(|channel| channel.sender.receiver_count() > 0)
+    {
+        return Ok(ChangeSubscription {
+            receiver: channel.sender.subscribe(),
+            pointers: Vec::new(),
+        });
+    }
+    let last = match last_observed(location) {
+        Some(observed) => Some(apply_env_overrides(
+            key,
+            serde_json::from_value::<T>(observed.snapshot)?,
+        )?),
+        None => None,
+    };
+    let (sender, receiver) = broadcast::channel(CAPACITY);
+    channels.insert(id, Box::new(Channel { sender, last }));
+    Ok(ChangeSubscription {
+        receiver,
+        pointers: Vec::new(),
+    })
+}
+
+/// Tell subscribers the config now has the value `new`, if it differs from what they last saw.
+pub(crate) fn publish<T: PersistableState>(location: &Path, new: &T, origin: ChangeOrigin) {
+    let mut channels = CHANNELS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let id = (TypeId::of::<T>(), location.to_path_buf());
+    let Some(channel) = channels
+        .get_mut(&id)
+        .and_then(|channel| channel.downcast_mut::<Channel<T>>())
+    else {
+        return;
+    };
+    if channel.sender.receiver_count() == 0 {
+        channels.remove(&id);
+        return;
+    }
+    let old = match (channel.last.replace(new.clone()), origin) {
+        (Some(old), _) => old,
+        // The first load only tells subscribers what the config is, it is not a change.
+        (None, ChangeOrigin::ExternalChange) => return,
+        (None, _) => T::default(),
+    };
+    if old == *new {
+        return;
+    }
+    let _ = channel.sender.send(ChangeEvent {
+        old,
+        new: new.clone(),
+        origin,
+    });
+}
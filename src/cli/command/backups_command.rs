@@ -0,0 +1,259 @@
+use crate::backups::Backup;
+use crate::backups::default_backup_retention;
+use crate::cli::config::known_projects::KnownProjects;
+use crate::cli::global_args::GlobalArgs;
+#[cfg(feature = "encryption")]
+use crate::cli::key_args::KeyArgs;
+use crate::file_lock::LockMode;
+use crate::format::Format;
+use crate::json_value;
+use crate::persistable_state::PersistableState;
+use crate::persistence_key::PersistenceKey;
+use crate::storage::blocking;
+use crate::storage::storage_backend;
+use clap::Parser;
+use clap::Subcommand;
+use cloud_terrastodon_user_input::Choice;
+use cloud_terrastodon_user_input::FzfArgs;
+use cloud_terrastodon_user_input::are_you_sure;
+use cloud_terrastodon_user_input::pick;
+use eyre::Context;
+use eyre::bail;
+use serde_json::Value;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Command to inspect and restore the `.bak` files kept next to a configuration.
+#[derive(Debug, Parser)]
+pub struct BackupsCommand {
+    #[command(subcommand)]
+    pub command: BackupsSubcommand,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum BackupsSubcommand {
+    /// List the backups of a configuration, newest first
+    List(BackupsListCommand),
+    /// Show the fields which differ between a backup and the current file
+    Diff(BackupsDiffCommand),
+    /// Replace the current file with a backup, backing up the current file first
+    Restore(BackupsRestoreCommand),
+}
+
+#[derive(Debug, Parser)]
+pub struct BackupsListCommand {
+    /// The configuration to list backups for, as JSON
+    #[clap(long, value_parser = parse_persistence_key)]
+    pub key: Option<PersistenceKey>,
+}
+
+#[derive(Debug, Parser)]
+pub struct BackupsDiffCommand {
+    /// The configuration to diff, as JSON
+    #[clap(long, value_parser = parse_persistence_key)]
+    pub key: Option<PersistenceKey>,
+    /// The backup file to compare, defaults to the newest when not interactive
+    #[clap(long)]
+    pub backup: Option<PathBuf>,
+    #[cfg(feature = "encryption")]
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct BackupsRestoreCommand {
+    /// The configuration to restore, as JSON
+    #[clap(long, value_parser = parse_persistence_key)]
+    pub key: Option<PersistenceKey>,
+    /// The backup file to restore, defaults to the newest when not interactive
+    #[clap(long)]
+    pub backup: Option<PathBuf>,
+    #[cfg(feature = "encryption")]
+    #[command(flatten)]
+    pub key_args: KeyArgs,
+}
+
+fn parse_persistence_key(s: &str) -> Result<PersistenceKey, String> {
+    serde_json::from_str::<PersistenceKey>(s)
+        .map_err(|e| format!("Failed to parse PersistenceKey: {e}"))
+}
+
+impl BackupsCommand {
+    pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
+        match self.command {
+            BackupsSubcommand::List(cmd) => cmd.handle(global_args).await,
+            BackupsSubcommand::Diff(cmd) => cmd.handle(global_args).await,
+            BackupsSubcommand::Restore(cmd) => cmd.handle(global_args).await,
+        }
+    }
+}
+
+impl BackupsListCommand {
+    pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
+        let key = pick_key(&global_args, self.key, "list").await?;
+        let backups = storage_backend().list_backups(&key)?;
+        let display = serde_json::to_string_pretty(&backups)?;
+        println!("{display}");
+        Ok(())
+    }
+}
+
+impl BackupsDiffCommand {
+    pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
+        let key = pick_key(&global_args, self.key, "diff").await?;
+        let backup = pick_backup(&global_args, &key, self.backup, "compare").await?;
+        #[cfg(feature = "encryption")]
+        let read = |path: &Path| self.key_args.read(path);
+        #[cfg(not(feature = "encryption"))]
+        let read = |path: &Path| eyre::Ok(std::fs::read(path)?);
+        let format = KnownProjects::load().await?.format_of(&key);
+        let old = read_json(format, &read(&backup.path)?, &backup.path)?;
+        let path = key.file_path()?;
+        let new = if key.exists().await? {
+            read_json(format, &read(&path)?, &path)?
+        } else {
+            Value::Null
+        };
+        let differences = json_value::diff(&old, &new);
+        if differences.is_empty() {
+            println!("{} matches {}", path.display(), backup.path.display());
+        }
+        for difference in differences {
+            println!("{difference}");
+        }
+        Ok(())
+    }
+}
+
+impl BackupsRestoreCommand {
+    pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
+        let key = pick_key(&global_args, self.key, "restore").await?;
+        let backup = pick_backup(&global_args, &key, self.backup, "restore").await?;
+        let path = key.file_path()?;
+        if global_args.interactive
+            && !global_args.auto_approve
+            && !are_you_sure(format!(
+                "Are you sure you want to replace {} with {}?",
+                path.display(),
+                backup.path.display()
+            ))?
+        {
+            bail!("Operation cancelled by user");
+        }
+
+        // Refuse to restore a backup which would not load, encrypted backups are restored as they are.
+        #[cfg(feature = "encryption")]
+        let plaintext = self.key_args.read(&backup.path)?;
+        #[cfg(not(feature = "encryption"))]
+        let plaintext = tokio::fs::read(&backup.path).await?;
+        let format = KnownProjects::load().await?.format_of(&key);
+        read_json(format, &plaintext, &backup.path)
+            .wrap_err_with(|| format!("Backup {} is not valid", backup.path.display()))?;
+        let content = tokio::fs::read(&backup.path).await?;
+
+        let storage = storage_backend();
+        blocking({
+            let key = key.clone();
+            move || {
+                let _lock = storage.lock(&key, LockMode::Exclusive)?;
+                if storage.exists(&key)? {
+                    let saved = storage.backup(&key, default_backup_retention())?;
+                    println!("Saved the current file to {}", saved.display());
+                }
+                // Keep the permissions of the current file, which may be restricted because the config is secret.
+                let mode = storage.mode(&key)?;
+                storage.write_with_mode(&key, &content, mode)
+            }
+        })
+        .await?;
+        println!("Restored {} from {}", path.display(), backup.path.display());
+        Ok(())
+    }
+}
+
+async fn pick_key(
+    global_args: &GlobalArgs,
+    key: Option<PersistenceKey>,
+    verb: &str,
+) -> eyre::Result<PersistenceKey> {
+    match (global_args.interactive, key) {
+        (_, Some(key)) => Ok(key),
+        (true, None) => {
+            let known_projects = KnownProjects::load().await?;
+            if known_projects.entries.is_empty() {
+                bail!("No projects found.");
+            }
+            Ok(pick(FzfArgs {
+                choices: known_projects
+                    .entries
+                    .iter()
+                    .map(|entry| {
+                        eyre::Ok(Choice {
+                            key: format!(
+                                "{} {} ({})",
+                                entry.key.kind,
+                                entry.key.file_path()?.display(),
+                                entry.last_accessed
+                            ),
+                            value: &entry.key,
+                        })
+                    })
+                    .collect::<eyre::Result<Vec<_>>>()?,
+                header: Some(format!("Select a project to {verb} backups for")),
+                ..Default::default()
+            })?
+            .clone())
+        }
+        (false, None) => {
+            bail!("The `backups {verb}` command requires either a key or interactivity");
+        }
+    }
+}
+
+async fn pick_backup(
+    global_args: &GlobalArgs,
+    key: &PersistenceKey,
+    path: Option<PathBuf>,
+    verb: &str,
+) -> eyre::Result<Backup> {
+    let config_path = key.file_path()?;
+    let backups = storage_backend().list_backups(key)?;
+    if let Some(path) = path {
+        // Accept either the full path or just the file name of the backup.
+        return backups
+            .into_iter()
+            .find(|backup| backup.path == path || backup.path.file_name() == Some(path.as_os_str()))
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "{} is not a backup of {}",
+                    path.display(),
+                    config_path.display()
+                )
+            });
+    }
+    if backups.is_empty() {
+        bail!("No backups found for {}", config_path.display());
+    }
+    if !global_args.interactive {
+        return Ok(backups.into_iter().next().expect("backups is not empty"));
+    }
+    Ok(pick(FzfArgs {
+        choices: backups
+            .into_iter()
+            .map(|backup| Choice {
+                key: format!("{} ({} bytes)", backup.path.display(), backup.len),
+                value: backup,
+            })
+            .collect(),
+        header: Some(format!("Select a backup to {verb}")),
+        ..Default::default()
+    })?
+    .value)
+}
+
+/// Parse the plaintext of a config or backup file as JSON, whatever its format.
+fn read_json(format: Format, content: &[u8], path: &Path) -> eyre::Result<Value> {
+    format
+        .to_json_value(&String::from_utf8_lossy(content))
+        .wrap_err_with(|| format!("Failed to parse {}", path.display()))
+}
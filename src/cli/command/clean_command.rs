@@ -1,6 +1,7 @@
 use crate::cli::config::known_projects::KnownProjects;
 use crate::cli::global_args::GlobalArgs;
 use crate::persistable_state::PersistableState;
+use crate::persistence_key::DirectoryKind;
 use crate::persistence_key::PersistenceKey;
 use clap::Parser;
 use cloud_terrastodon_user_input::Choice;
@@ -15,6 +16,9 @@ pub struct CleanCommand {
     /// Optionally provide a value to parse as JSON for display
     #[clap(long, value_parser = parse_persistence_key)]
     pub key: Option<PersistenceKey>,
+    /// Only offer files stored in this kind of directory
+    #[clap(long, value_enum)]
+    pub kind: Option<DirectoryKind>,
 }
 
 fn parse_persistence_key(s: &str) -> Result<PersistenceKey, String> {
@@ -23,7 +27,7 @@ fn parse_persistence_key(s: &str) -> Result<PersistenceKey, String> {
 }
 impl CleanCommand {
     pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
-        let mut known_projects = KnownProjects::load().await?;
+        let known_projects = KnownProjects::load().await?;
         let keys = match (global_args.interactive, self.key) {
             (true, Some(key)) => vec![key],
             (true, None) => {
@@ -32,10 +36,12 @@ impl CleanCommand {
                     choices: known_projects
                         .entries
                         .iter()
+                        .filter(|entry| self.kind.is_none_or(|kind| entry.key.kind == kind))
                         .map(|entry| {
                             eyre::Result::<Choice<&PersistenceKey>>::Ok(Choice {
                                 key: format!(
-                                    "{} ({})",
+                                    "{} {} ({})",
+                                    entry.key.kind,
                                     entry.key.file_path()?.display(),
                                     entry.last_accessed
                                 ),
@@ -67,8 +73,10 @@ impl CleanCommand {
                 bail!("Operation cancelled by user");
             }
             tokio::fs::remove_file(path_to_remove).await?;
-            known_projects.entries.retain(|entry| entry.key != key);
-            known_projects.save().await?;
+            KnownProjects::update(|known_projects| {
+                known_projects.entries.retain(|entry| entry.key != key)
+            })
+            .await?;
         }
         Ok(())
     }
@@ -1,3 +1,4 @@
+use super::backups_command::BackupsCommand;
 use super::clean_command::CleanCommand;
 use super::list_command::ListCommand;
 use super::prune_command::PruneCommand;
@@ -16,6 +17,8 @@ pub enum Command {
     Clean(CleanCommand),
     /// Clean up known configuration entries which are no longer valid
     Prune(PruneCommand),
+    /// List, compare and restore configuration backups
+    Backups(BackupsCommand),
 }
 impl Command {
     pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
@@ -24,6 +27,7 @@ impl Command {
             Command::Show(cmd) => cmd.handle(global_args).await,
             Command::Clean(cmd) => cmd.handle(global_args).await,
             Command::Prune(cmd) => cmd.handle(global_args).await,
+            Command::Backups(cmd) => cmd.handle(global_args).await,
         }
     }
 }
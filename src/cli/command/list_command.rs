@@ -2,12 +2,18 @@ use crate::cli::config::known_projects::KnownProjectEntry;
 use crate::cli::config::known_projects::KnownProjects;
 use crate::cli::global_args::GlobalArgs;
 use crate::persistable_state::PersistableState;
+use crate::persistence_key::DirectoryKind;
 use clap::Parser;
+use serde_json::json;
 use std::collections::HashMap;
 use std::iter::once;
 
 #[derive(Debug, Parser)]
-pub struct ListCommand {}
+pub struct ListCommand {
+    /// Only list files stored in this kind of directory
+    #[clap(long, value_enum)]
+    pub kind: Option<DirectoryKind>,
+}
 
 impl ListCommand {
     pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
@@ -20,8 +26,20 @@ impl ListCommand {
                 .chain(once(KnownProjectEntry {
                     key: KnownProjects::key().await?,
                     last_accessed: chrono::Local::now(),
+                    format: None,
                 }))
-                .map(|entry| Ok((entry.key.file_path()?.display().to_string(), entry)))
+                .filter(|entry| self.kind.is_none_or(|kind| entry.key.kind == kind))
+                .map(|entry| {
+                    Ok((
+                        entry.key.file_path()?.display().to_string(),
+                        json!({
+                            "format": entry.format(),
+                            "kind": entry.key.kind,
+                            "key": entry.key,
+                            "last_accessed": entry.last_accessed,
+                        }),
+                    ))
+                })
                 .collect::<eyre::Result<HashMap<_, _>>>()?,
         )?;
         println!("{display}");
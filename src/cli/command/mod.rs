@@ -1,7 +1,9 @@
+pub mod backups_command;
 pub mod clean_command;
 pub mod list_command;
 pub mod prune_command;
 pub mod show_command;
 
+#[allow(clippy::module_inception)]
 mod command;
 pub use command::*;
@@ -1,30 +1,49 @@
 use crate::cli::config::known_projects::KnownProjects;
 use crate::cli::global_args::GlobalArgs;
 use crate::persistable_state::PersistableState;
+use crate::persistence_key::DirectoryKind;
 use clap::Parser;
 use tracing::warn;
 
 /// Command to prune unused or obsolete configurations.
 #[derive(Debug, Parser)]
-pub struct PruneCommand {}
+pub struct PruneCommand {
+    /// Only prune entries for files stored in this kind of directory
+    #[clap(long, value_enum)]
+    pub kind: Option<DirectoryKind>,
+}
 
 impl PruneCommand {
     pub async fn handle(self, global_args: GlobalArgs) -> eyre::Result<()> {
         let _ = global_args;
-        let mut known_projects = KnownProjects::load().await?;
-        let mut remaining = Vec::new();
+        let known_projects = KnownProjects::load().await?;
+        let mut missing = Vec::new();
         for entry in known_projects.entries {
-            if !entry.key.exists().await? {
+            if self.kind.is_some_and(|kind| entry.key.kind != kind) {
+                continue;
+            }
+            // The runtime directory may be unavailable, such as outside a login session.
+            let exists = match entry.key.exists().await {
+                Ok(exists) => exists,
+                Err(err) => {
+                    warn!("Skipping entry {:?}: {err}", entry.key);
+                    continue;
+                }
+            };
+            if !exists {
                 warn!(
                     "Removing entry for non-existent project: {}",
                     entry.key.file_path()?.display()
                 );
-            } else {
-                remaining.push(entry);
+                missing.push(entry.key);
             }
         }
-        known_projects.entries = remaining;
-        known_projects.save().await?;
+        KnownProjects::update(|known_projects| {
+            known_projects
+                .entries
+                .retain(|entry| !missing.contains(&entry.key))
+        })
+        .await?;
 
         Ok(())
     }
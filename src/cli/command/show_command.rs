@@ -1,5 +1,8 @@
 use crate::cli::config::known_projects::KnownProjects;
 use crate::cli::global_args::GlobalArgs;
+#[cfg(feature = "encryption")]
+use crate::cli::key_args::KeyArgs;
+use crate::env_overrides::env_overrides;
 use crate::persistable_state::PersistableState;
 use crate::persistence_key::PersistenceKey;
 use clap::Parser;
@@ -15,6 +18,9 @@ pub struct ShowCommand {
     /// Optionally provide a value to parse as JSON for display
     #[clap(long, value_parser = parse_persistence_key)]
     pub key: Option<PersistenceKey>,
+    #[cfg(feature = "encryption")]
+    #[command(flatten)]
+    pub key_args: KeyArgs,
 }
 
 fn parse_persistence_key(s: &str) -> Result<PersistenceKey, String> {
@@ -39,7 +45,8 @@ impl ShowCommand {
                         .map(|entry| {
                             eyre::Ok(Choice {
                                 key: format!(
-                                    "{} ({})",
+                                    "{} {} ({})",
+                                    entry.key.kind,
                                     entry.key.file_path()?.display(),
                                     entry.last_accessed
                                 ),
@@ -58,15 +65,30 @@ impl ShowCommand {
                 bail!("The `show` command requires either a key or interactivity");
             }
         };
-        let last_accessed = known_projects
+        let entry = known_projects
             .entries
             .iter()
             .find(|entry| entry.key == key)
             .ok_or_else(|| eyre::eyre!("No project found for the provided key"))?;
+        let path = key.file_path()?;
+        let format = entry.format();
+        let contents = if key.exists().await? {
+            #[cfg(feature = "encryption")]
+            let content = self.key_args.read(&path)?;
+            #[cfg(not(feature = "encryption"))]
+            let content = tokio::fs::read(&path).await?;
+            format.to_json_value(&String::from_utf8_lossy(&content))?
+        } else {
+            serde_json::Value::Null
+        };
         let display = serde_json::to_string_pretty(&json!({
             "key": key,
-            "last_accessed": last_accessed,
-            "file_path": key.file_path()?.display().to_string(),
+            "last_accessed": entry,
+            "file_path": path.display().to_string(),
+            "format": format,
+            "kind": key.kind,
+            "env_overrides": env_overrides(&key.env_prefix(), &contents),
+            "contents": contents,
         }))?;
         println!("{display}");
         Ok(())
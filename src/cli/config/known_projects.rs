@@ -1,4 +1,6 @@
 use super::project::PROJECT;
+use crate::blocking::BlockingPersistableState;
+use crate::format::Format;
 use crate::persistable_state::PersistableState;
 use crate::persistence_key::PersistenceKey;
 use chrono::DateTime;
@@ -15,6 +17,16 @@ pub struct KnownProjects {
 pub struct KnownProjectEntry {
     pub key: PersistenceKey,
     pub last_accessed: DateTime<Local>,
+    /// The format the config is stored in, missing from entries tracked by older versions.
+    #[serde(default)]
+    pub format: Option<Format>,
+}
+
+impl KnownProjectEntry {
+    /// The format the config is stored in, falling back to the extension of its file slug.
+    pub fn format(&self) -> Format {
+        self.format.unwrap_or_else(|| self.key.format())
+    }
 }
 
 #[async_trait::async_trait]
@@ -29,22 +41,44 @@ impl PersistableState for KnownProjects {
 
 impl KnownProjects {
     #[async_recursion::async_recursion]
-    pub async fn track_project_accessed(key: PersistenceKey) -> eyre::Result<()> {
+    pub async fn track_project_accessed(key: PersistenceKey, format: Format) -> eyre::Result<()> {
+        let now = Local::now();
+        KnownProjects::update(|known_projects| known_projects.mark_accessed(key, format, now))
+            .await?;
+        Ok(())
+    }
+
+    pub fn track_project_accessed_blocking(
+        key: PersistenceKey,
+        format: Format,
+    ) -> eyre::Result<()> {
         let now = Local::now();
-        let mut known_projects = KnownProjects::load().await?;
-        let entry = known_projects
-            .entries
-            .iter_mut()
-            .find(|entry| entry.key == key);
+        KnownProjects::update_blocking(|known_projects| {
+            known_projects.mark_accessed(key, format, now)
+        })?;
+        Ok(())
+    }
+
+    /// The format a config is stored in, falling back to the extension of its file slug if it is not tracked.
+    pub fn format_of(&self, key: &PersistenceKey) -> Format {
+        self.entries
+            .iter()
+            .find(|entry| entry.key == *key)
+            .map(KnownProjectEntry::format)
+            .unwrap_or_else(|| key.format())
+    }
+
+    fn mark_accessed(&mut self, key: PersistenceKey, format: Format, now: DateTime<Local>) {
+        let entry = self.entries.iter_mut().find(|entry| entry.key == key);
         if let Some(existing_entry) = entry {
             existing_entry.last_accessed = now;
+            existing_entry.format = Some(format);
         } else {
-            known_projects.entries.push(KnownProjectEntry {
+            self.entries.push(KnownProjectEntry {
                 key,
                 last_accessed: now,
+                format: Some(format),
             });
         }
-        known_projects.save().await?;
-        Ok(())
     }
 }
@@ -0,0 +1,48 @@
+use crate::encryption::KeySource;
+use crate::encryption::decrypt;
+use crate::encryption::is_encrypted;
+use clap::Args;
+use eyre::bail;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The key used to read encrypted configurations, encrypted contents are never shown without it.
+#[derive(Debug, Args, Clone, Default)]
+pub struct KeyArgs {
+    /// Decrypt using the contents of this keyfile
+    #[arg(long, conflicts_with_all = ["key_env", "passphrase"])]
+    pub key_file: Option<PathBuf>,
+    /// Decrypt using the passphrase in this environment variable
+    #[arg(long, conflicts_with = "passphrase")]
+    pub key_env: Option<String>,
+    /// Decrypt using a passphrase entered at a prompt
+    #[arg(long, default_value_t = false)]
+    pub passphrase: bool,
+}
+
+impl KeyArgs {
+    pub fn key_source(&self) -> Option<KeySource> {
+        if let Some(path) = &self.key_file {
+            return Some(KeySource::Keyfile(path.clone()));
+        }
+        if let Some(name) = &self.key_env {
+            return Some(KeySource::Env(name.clone()));
+        }
+        self.passphrase.then_some(KeySource::Passphrase)
+    }
+
+    /// The plaintext of a config or backup file, refusing encrypted files unless a key was given.
+    pub fn read(&self, path: &Path) -> eyre::Result<Vec<u8>> {
+        let content = std::fs::read(path)?;
+        if !is_encrypted(&content) {
+            return Ok(content);
+        }
+        match self.key_source() {
+            Some(source) => decrypt(&source, path, &content),
+            None => bail!(
+                "{} is encrypted, pass --key-file, --key-env or --passphrase to read it",
+                path.display()
+            ),
+        }
+    }
+}
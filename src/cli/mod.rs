@@ -3,3 +3,5 @@ pub mod command;
 pub mod config;
 pub mod global_args;
 pub mod init_tracing;
+#[cfg(feature = "encryption")]
+pub mod key_args;
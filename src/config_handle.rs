@@ -0,0 +1,291 @@
+use crate::conflict::reapply;
+use crate::persistable_state::PersistableState;
+use crate::persistable_state::save_state;
+use crate::persistence_key::PersistenceKey;
+use crate::redact::REDACTED;
+use crate::redact::describe_error;
+use crate::storage::StorageBackend;
+use crate::storage::blocking;
+use std::any::Any;
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+use std::sync::Weak;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tracing::debug;
+use tracing::error;
+
+/// How long a config must go without changes before it is saved in the background.
+pub const AUTOSAVE_DELAY: Duration = Duration::from_millis(500);
+
+/// Identifies a cached config by its type and storage location.
+type HandleId = (TypeId, PathBuf);
+
+/// The live handles, so every handle to a config shares one copy.
+static HANDLES: LazyLock<Mutex<HashMap<HandleId, Weak<dyn Any + Send + Sync>>>> =
+    LazyLock::new(Default::default);
+
+/// A cached config shared by every handle for the same key in the process, cheap to clone.
+///
+/// The config is loaded once, changes made through [`write`](ConfigHandle::write) are saved in the
+/// background once they settle for [`AUTOSAVE_DELAY`]. Call [`flush`](ConfigHandle::flush) before
+/// shutting down to make sure the last changes are saved, they are also saved when the last handle
+/// is dropped but errors can then only be logged.
+///
+/// ```ignore
+/// let handle = ConfigHandle::<Settings>::get().await?;
+/// handle.write().theme = "dark".to_string();
+/// println!("{}", handle.read().theme);
+/// handle.flush().await?;
+/// ```
+pub struct ConfigHandle<T: PersistableState> {
+    shared: Arc<Shared<T>>,
+}
+
+struct Shared<T: PersistableState> {
+    key: PersistenceKey,
+    storage: Arc<dyn StorageBackend>,
+    state: RwLock<T>,
+    /// Incremented by every write, compared with `saved` to tell whether there are unsaved changes.
+    changes: AtomicU64,
+    saved: AtomicU64,
+    autosave_scheduled: AtomicBool,
+    /// Only one save runs at a time, so an older snapshot never overwrites a newer one.
+    saving: tokio::sync::Mutex<()>,
+    runtime: tokio::runtime::Handle,
+}
+
+impl<T: PersistableState> Clone for ConfigHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T: PersistableState> std::fmt::Debug for ConfigHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut debug = f.debug_struct("ConfigHandle");
+        debug.field("key", &self.shared.key);
+        match T::is_secret() {
+            true => debug.field("state", &format_args!("{REDACTED}")),
+            false => debug.field("state", &*self.read()),
+        };
+        debug.finish()
+    }
+}
+
+impl<T: PersistableState> ConfigHandle<T> {
+    /// The handle for the type's key, loading the config unless another handle to it is alive.
+    pub async fn get() -> eyre::Result<Self> {
+        let key = T::key().await?;
+        let storage = T::storage();
+        let id = (TypeId::of::<T>(), storage.location(&key)?);
+        if let Some(shared) = Self::live(&id) {
+            return Ok(Self { shared });
+        }
+
+        let state = T::load().await?;
+        let mut handles = HANDLES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Another task may have loaded the config while this one was loading it.
+        if let Some(shared) = handles.get(&id).and_then(upgrade) {
+            return Ok(Self { shared });
+        }
+        let shared = Arc::new(Shared {
+            key,
+            storage,
+            state: RwLock::new(state),
+            changes: AtomicU64::new(0),
+            saved: AtomicU64::new(0),
+            autosave_scheduled: AtomicBool::new(false),
+            saving: tokio::sync::Mutex::new(()),
+            runtime: tokio::runtime::Handle::current(),
+        });
+        handles.retain(|_, weak| weak.strong_count() > 0);
+        let any: Arc<dyn Any + Send + Sync> = shared.clone();
+        handles.insert(id, Arc::downgrade(&any));
+        Ok(Self { shared })
+    }
+
+    fn live(id: &HandleId) -> Option<Arc<Shared<T>>> {
+        HANDLES
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(id)
+            .and_then(upgrade)
+    }
+
+    pub fn key(&self) -> &PersistenceKey {
+        &self.shared.key
+    }
+
+    /// Read the cached config, without touching the file.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.shared
+            .state
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Modify the cached config, scheduling a save once the guard is dropped.
+    pub fn write(&self) -> ConfigWriteGuard<'_, T> {
+        ConfigWriteGuard {
+            guard: self
+                .shared
+                .state
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            shared: &self.shared,
+        }
+    }
+
+    /// Save any unsaved changes now, waiting for a save already in progress.
+    pub async fn flush(&self) -> eyre::Result<()> {
+        self.shared.clone().save().await
+    }
+}
+
+fn upgrade<T: PersistableState>(weak: &Weak<dyn Any + Send + Sync>) -> Option<Arc<Shared<T>>> {
+    weak.upgrade()?.downcast().ok()
+}
+
+impl<T: PersistableState> Shared<T> {
+    fn has_unsaved_changes(&self) -> bool {
+        self.changes.load(Ordering::Acquire) != self.saved.load(Ordering::Acquire)
+    }
+
+    async fn save(self: Arc<Self>) -> eyre::Result<()> {
+        let _saving = self.saving.lock().await;
+        let changes = self.changes.load(Ordering::Acquire);
+        if changes == self.saved.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let state = self
+            .state
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        let storage = self.storage.clone();
+        let key = self.key.clone();
+        let (state, merged) = blocking(move || {
+            let merged = save_state(&*storage, &key, &state)?;
+            Ok((state, merged))
+        })
+        .await?;
+        if let Some(merged) = merged {
+            self.adopt(&state, merged, changes)?;
+        }
+        self.saved.store(changes, Ordering::Release);
+        Ok(())
+    }
+
+    /// Replace the cached config with the `merged` result of saving `saved`,
+    /// keeping any writes made to the cache since.
+    fn adopt(&self, saved: &T, merged: T, changes: u64) -> eyre::Result<()> {
+        let mut state = self
+            .state
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Writes bump `changes` while still holding the lock, so none are in progress here.
+        *state = if self.changes.load(Ordering::Acquire) == changes {
+            merged
+        } else {
+            let newer = reapply(
+                &serde_json::to_value(saved)?,
+                &serde_json::to_value(&*state)?,
+                serde_json::to_value(merged)?,
+            );
+            serde_json::from_value(newer)?
+        };
+        Ok(())
+    }
+
+    fn schedule_autosave(self: &Arc<Self>) {
+        self.changes.fetch_add(1, Ordering::AcqRel);
+        if self.autosave_scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let shared = self.clone();
+        self.runtime.spawn(async move {
+            // Wait until the config stops changing, so a burst of writes is saved once.
+            loop {
+                let changes = shared.changes.load(Ordering::Acquire);
+                tokio::time::sleep(AUTOSAVE_DELAY).await;
+                if shared.changes.load(Ordering::Acquire) == changes {
+                    break;
+                }
+            }
+            shared.autosave_scheduled.store(false, Ordering::Release);
+            let key = shared.key.clone();
+            if let Err(err) = shared.save().await {
+                error!(
+                    "Failed to autosave config {key:?}: {}",
+                    describe_error::<T>(&err)
+                );
+            }
+        });
+    }
+}
+
+impl<T: PersistableState> Drop for Shared<T> {
+    fn drop(&mut self) {
+        if !self.has_unsaved_changes() {
+            return;
+        }
+        debug!(
+            "Saving config {:?} as its last handle was dropped",
+            self.key
+        );
+        let state = self
+            .state
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Err(err) = save_state(&*self.storage, &self.key, state) {
+            error!(
+                "Failed to save config {:?} on drop: {}",
+                self.key,
+                describe_error::<T>(&err)
+            );
+        }
+    }
+}
+
+/// Returned by [`ConfigHandle::write`], the config is saved in the background after this is dropped.
+pub struct ConfigWriteGuard<'a, T: PersistableState> {
+    guard: RwLockWriteGuard<'a, T>,
+    shared: &'a Arc<Shared<T>>,
+}
+
+impl<T: PersistableState> Deref for ConfigWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: PersistableState> DerefMut for ConfigWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T: PersistableState> Drop for ConfigWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // The guard is still held here, the autosave waits before reading the config.
+        self.shared.schedule_autosave();
+    }
+}
@@ -0,0 +1,124 @@
+use crate::persistence_key::PersistenceKey;
+use crate::storage::StorageBackend;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::hash::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+
+/// What to do when a config file changed on disk since this process last read or wrote it.
+///
+/// Saves made by this process are never conflicts, even from values loaded before the save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Refuse to save, returning a [`ConflictError`].
+    #[default]
+    Fail,
+    /// Save anyway, discarding the external changes.
+    Overwrite,
+    /// Read the file again and apply the in-memory changes on top of it,
+    /// so fields that were only changed externally keep their new values.
+    /// `modify_and_save` and [`ConfigHandle`](crate::config_handle::ConfigHandle) adopt the merged state.
+    ReloadAndReapply,
+}
+
+/// Returned by `save` when the file was modified externally and the policy is [`ConflictPolicy::Fail`].
+#[derive(Debug)]
+pub struct ConflictError {
+    pub path: PathBuf,
+}
+
+impl std::fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Config {} was modified by someone else since it was loaded, refusing to overwrite it",
+            self.path.display()
+        )
+    }
+}
+
+impl std::error::Error for ConflictError {}
+
+/// Identifies a particular version of a stored config by its contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    /// Fingerprint the stored config, or `None` if it does not exist.
+    pub fn read(storage: &dyn StorageBackend, key: &PersistenceKey) -> eyre::Result<Option<Self>> {
+        Ok(storage.read(key)?.map(|content| Self::new(&content)))
+    }
+
+    pub fn new(content: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        Self {
+            len: content.len() as u64,
+            hash: hasher.finish(),
+        }
+    }
+}
+
+/// The last version of a file this process read or wrote.
+#[derive(Debug, Clone)]
+pub(crate) struct Observed {
+    pub fingerprint: Option<Fingerprint>,
+    /// The state as it was when observed, used as the base when reapplying changes.
+    pub snapshot: Value,
+}
+
+static OBSERVED: LazyLock<Mutex<HashMap<PathBuf, Observed>>> = LazyLock::new(Default::default);
+
+pub(crate) fn record_observed(path: &Path, observed: Observed) {
+    OBSERVED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(path.to_path_buf(), observed);
+}
+
+pub(crate) fn last_observed(path: &Path) -> Option<Observed> {
+    OBSERVED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(path)
+        .cloned()
+}
+
+/// Apply the changes made between `base` and `ours` on top of `theirs`.
+///
+/// Objects are merged key by key, anything else that we changed replaces their value wholesale.
+pub fn reapply(base: &Value, ours: &Value, theirs: Value) -> Value {
+    if base == ours {
+        return theirs;
+    }
+    match (base, ours, theirs) {
+        (Value::Object(base), Value::Object(ours), Value::Object(mut theirs)) => {
+            for (key, ours_value) in ours {
+                let merged = match (base.get(key), theirs.get(key)) {
+                    (Some(base_value), Some(theirs_value)) => {
+                        reapply(base_value, ours_value, theirs_value.clone())
+                    }
+                    // They removed a field we did not touch.
+                    (Some(base_value), None) if base_value == ours_value => continue,
+                    _ => ours_value.clone(),
+                };
+                theirs.insert(key.clone(), merged);
+            }
+            for key in base.keys() {
+                if !ours.contains_key(key) {
+                    theirs.shift_remove(key);
+                }
+            }
+            Value::Object(theirs)
+        }
+        (_, ours, _) => ours.clone(),
+    }
+}
@@ -0,0 +1,287 @@
+//! Encrypting configs at rest with XChaCha20-Poly1305, using a key derived with Argon2id.
+//!
+//! Encryption is opted into per type by wrapping the backend returned from `PersistableState::storage`:
+//!
+//! ```ignore
+//! impl PersistableState for ApiCredentials {
+//!     async fn key() -> eyre::Result<PersistenceKey> {
+//!         Ok(PersistenceKey::new("my_project", "credentials.json"))
+//!     }
+//!
+//!     fn storage() -> Arc<dyn StorageBackend> {
+//!         Arc::new(EncryptedBackend::new(
+//!             storage_backend(),
+//!             KeySource::Env("MY_PROJECT_KEY".to_string()),
+//!         ))
+//!     }
+//! }
+//! ```
+
+use crate::backups::Backup;
+use crate::backups::BackupRetention;
+use crate::file_lock::LockMode;
+use crate::persistence_key::DirectoryKind;
+use crate::persistence_key::PersistenceKey;
+use crate::redact::Secret;
+use crate::storage::StorageBackend;
+use crate::storage::StorageLock;
+use argon2::Argon2;
+use chacha20poly1305::Key;
+use chacha20poly1305::KeyInit;
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::XNonce;
+use chacha20poly1305::aead::Aead;
+use chacha20poly1305::aead::Payload;
+use eyre::Context;
+use eyre::bail;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// The start of every encrypted config, followed by the salt, the nonce and the ciphertext.
+const MAGIC: &[u8] = b"eye_config encrypted v1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Passphrases entered at the prompt, by the location they were asked for.
+static PASSPHRASES: LazyLock<Mutex<HashMap<PathBuf, Secret<Vec<u8>>>>> =
+    LazyLock::new(Default::default);
+
+/// A derived key along with the key material it was derived from.
+type DerivedKey = (Secret<Vec<u8>>, Key);
+
+/// Derived keys by salt, Argon2 is slow on purpose so each key is only derived once per process.
+static DERIVED_KEYS: LazyLock<Mutex<HashMap<[u8; SALT_LEN], DerivedKey>>> =
+    LazyLock::new(Default::default);
+
+/// Where the key material for encrypted configs comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    /// A file whose whole contents are the key material, such as 32 random bytes.
+    Keyfile(PathBuf),
+    /// An environment variable holding a passphrase.
+    Env(String),
+    /// Ask for a passphrase on the terminal, once per config for the rest of the process.
+    Passphrase,
+}
+
+impl KeySource {
+    fn secret(&self, location: &Path) -> eyre::Result<Secret<Vec<u8>>> {
+        let secret = match self {
+            KeySource::Keyfile(path) => std::fs::read(path)
+                .wrap_err_with(|| format!("Failed to read the keyfile {}", path.display()))?,
+            KeySource::Env(name) => std::env::var(name)
+                .wrap_err_with(|| format!("Failed to read the key from ${name}"))?
+                .into_bytes(),
+            KeySource::Passphrase => {
+                let mut passphrases = PASSPHRASES
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                if let Some(passphrase) = passphrases.get(location) {
+                    return Ok(passphrase.clone());
+                }
+                let passphrase =
+                    rpassword::prompt_password(format!("Passphrase for {}: ", location.display()))
+                        .wrap_err("Failed to read the passphrase")?
+                        .into_bytes();
+                passphrases.insert(location.to_path_buf(), Secret::new(passphrase.clone()));
+                passphrase
+            }
+        };
+        if secret.is_empty() {
+            bail!("The key for {} is empty", location.display());
+        }
+        Ok(Secret::new(secret))
+    }
+
+    /// Forget a passphrase which failed to decrypt, so the next attempt asks again.
+    fn forget(&self, location: &Path) {
+        if *self == KeySource::Passphrase {
+            PASSPHRASES
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .remove(location);
+        }
+    }
+}
+
+/// Whether the contents are an encrypted config.
+pub fn is_encrypted(content: &[u8]) -> bool {
+    content.starts_with(MAGIC)
+}
+
+/// Encrypt a config, `location` is only used to prompt for a passphrase.
+pub fn encrypt(source: &KeySource, location: &Path, plaintext: &[u8]) -> eyre::Result<Vec<u8>> {
+    let mut salt = [0; SALT_LEN];
+    getrandom::fill(&mut salt)?;
+    encrypt_with_salt(source, location, plaintext, salt)
+}
+
+/// Decrypt a config, see [`encrypt`].
+pub fn decrypt(source: &KeySource, location: &Path, content: &[u8]) -> eyre::Result<Vec<u8>> {
+    let Some(body) = content.strip_prefix(MAGIC) else {
+        bail!("{} is not encrypted", location.display());
+    };
+    if body.len() < SALT_LEN + NONCE_LEN {
+        bail!("{} is truncated", location.display());
+    }
+    let (salt, body) = body.split_at(SALT_LEN);
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    let salt: [u8; SALT_LEN] = salt.try_into().expect("split at the salt length");
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at the nonce length");
+    let key = derive_key(source, location, salt)?;
+    XChaCha20Poly1305::new(&key)
+        .decrypt(
+            &XNonce::from(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: &content[..MAGIC.len() + SALT_LEN],
+            },
+        )
+        .map_err(|_| {
+            source.forget(location);
+            eyre::eyre!(
+                "Failed to decrypt {}, the key is wrong or the file was modified",
+                location.display()
+            )
+        })
+}
+
+fn encrypt_with_salt(
+    source: &KeySource,
+    location: &Path,
+    plaintext: &[u8],
+    salt: [u8; SALT_LEN],
+) -> eyre::Result<Vec<u8>> {
+    let key = derive_key(source, location, salt)?;
+    let mut nonce = [0; NONCE_LEN];
+    getrandom::fill(&mut nonce)?;
+    let mut content = [MAGIC, &salt].concat();
+    let ciphertext = XChaCha20Poly1305::new(&key)
+        .encrypt(
+            &XNonce::from(nonce),
+            Payload {
+                msg: plaintext,
+                aad: &content,
+            },
+        )
+        .map_err(|_| eyre::eyre!("Failed to encrypt {}", location.display()))?;
+    content.extend_from_slice(&nonce);
+    content.extend_from_slice(&ciphertext);
+    Ok(content)
+}
+
+fn derive_key(source: &KeySource, location: &Path, salt: [u8; SALT_LEN]) -> eyre::Result<Key> {
+    let secret = source.secret(location)?;
+    let mut derived_keys = DERIVED_KEYS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some((derived_from, key)) = derived_keys.get(&salt)
+        && *derived_from == secret
+    {
+        return Ok(*key);
+    }
+    let mut key = Key::default();
+    Argon2::default()
+        .hash_password_into(secret.expose(), &salt, &mut key)
+        .map_err(|err| eyre::eyre!("Failed to derive the key for {}: {err}", location.display()))?;
+    derived_keys.insert(salt, (secret, key));
+    Ok(key)
+}
+
+/// Encrypts configs before handing them to another backend, and decrypts them when read.
+///
+/// Backups are copies of the encrypted contents. A config stored before encryption was enabled
+/// is read as is and encrypted the next time it is saved.
+#[derive(Debug)]
+pub struct EncryptedBackend {
+    inner: Arc<dyn StorageBackend>,
+    source: KeySource,
+}
+
+impl EncryptedBackend {
+    pub fn new(inner: Arc<dyn StorageBackend>, source: KeySource) -> Self {
+        Self { inner, source }
+    }
+}
+
+impl StorageBackend for EncryptedBackend {
+    fn location(&self, key: &PersistenceKey) -> eyre::Result<PathBuf> {
+        self.inner.location(key)
+    }
+
+    fn read(&self, key: &PersistenceKey) -> eyre::Result<Option<Vec<u8>>> {
+        let Some(content) = self.inner.read(key)? else {
+            return Ok(None);
+        };
+        let location = self.inner.location(key)?;
+        if !is_encrypted(&content) {
+            warn!(
+                "{} is not encrypted yet, it will be encrypted when next saved",
+                location.display()
+            );
+            return Ok(Some(content));
+        }
+        Ok(Some(decrypt(&self.source, &location, &content)?))
+    }
+
+    fn write(&self, key: &PersistenceKey, contents: &[u8]) -> eyre::Result<()> {
+        self.write_with_mode(key, contents, None)
+    }
+
+    fn write_with_mode(
+        &self,
+        key: &PersistenceKey,
+        contents: &[u8],
+        mode: Option<u32>,
+    ) -> eyre::Result<()> {
+        let location = self.inner.location(key)?;
+        // Keep the salt of the stored config so the derived key is reused.
+        let salt = match self.inner.read(key)? {
+            Some(stored) if is_encrypted(&stored) && stored.len() >= MAGIC.len() + SALT_LEN => {
+                stored[MAGIC.len()..MAGIC.len() + SALT_LEN]
+                    .try_into()
+                    .expect("sliced to the salt length")
+            }
+            _ => {
+                let mut salt = [0; SALT_LEN];
+                getrandom::fill(&mut salt)?;
+                salt
+            }
+        };
+        let encrypted = encrypt_with_salt(&self.source, &location, contents, salt)?;
+        self.inner.write_with_mode(key, &encrypted, mode)
+    }
+
+    fn mode(&self, key: &PersistenceKey) -> eyre::Result<Option<u32>> {
+        self.inner.mode(key)
+    }
+
+    fn exists(&self, key: &PersistenceKey) -> eyre::Result<bool> {
+        self.inner.exists(key)
+    }
+
+    fn remove(&self, key: &PersistenceKey) -> eyre::Result<()> {
+        self.inner.remove(key)
+    }
+
+    fn list(&self, project_name: &Path, kind: DirectoryKind) -> eyre::Result<Vec<PersistenceKey>> {
+        self.inner.list(project_name, kind)
+    }
+
+    fn backup(&self, key: &PersistenceKey, retention: BackupRetention) -> eyre::Result<PathBuf> {
+        self.inner.backup(key, retention)
+    }
+
+    fn list_backups(&self, key: &PersistenceKey) -> eyre::Result<Vec<Backup>> {
+        self.inner.list_backups(key)
+    }
+
+    fn lock(&self, key: &PersistenceKey, mode: LockMode) -> eyre::Result<StorageLock> {
+        self.inner.lock(key, mode)
+    }
+}
@@ -0,0 +1,246 @@
+use crate::conflict::last_observed;
+use crate::json_value;
+use crate::json_value::escape;
+use crate::persistable_state::PersistableState;
+use crate::persistence_key::PersistenceKey;
+use crate::redact::redact_error;
+use eyre::Context;
+use itertools::Itertools;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::path::Path;
+use tracing::debug;
+
+/// Separates the prefix and nested field names in an override variable name.
+pub const SEPARATOR: &str = "__";
+
+/// A config field overridden by an environment variable.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EnvOverride {
+    pub var: String,
+    /// JSON pointer to the overridden field.
+    pub pointer: String,
+    pub value: Value,
+    /// The value of the variable before parsing.
+    pub raw: String,
+}
+
+/// Collect overrides from variables named `{prefix}__{field}__{nested_field}`.
+///
+/// Field names are matched case-insensitively against the fields present in `template`,
+/// and unknown fields are lowercased. Values of fields holding a string in `template` are kept as
+/// strings, others are parsed as JSON scalars, falling back to plain strings.
+pub fn env_overrides(prefix: &str, template: &Value) -> Vec<EnvOverride> {
+    let prefix = format!("{prefix}{SEPARATOR}");
+    let mut overrides = std::env::vars_os()
+        .filter_map(|(var, value)| Some((var.into_string().ok()?, value.into_string().ok()?)))
+        .filter_map(|(var, raw)| {
+            let fields = var.strip_prefix(&prefix)?;
+            let pointer = pointer_for(fields, template)?;
+            Some(EnvOverride {
+                value: parse_value(&raw, template.pointer(&pointer)),
+                pointer,
+                var,
+                raw,
+            })
+        })
+        .collect::<Vec<_>>();
+    overrides.sort_by(|a, b| a.var.cmp(&b.var));
+    overrides
+}
+
+/// Apply the environment overrides for a config on top of the values read from its file.
+pub fn apply_env_overrides<T: PersistableState>(key: &PersistenceKey, state: T) -> eyre::Result<T> {
+    let Some(prefix) = T::env_prefix(key) else {
+        return Ok(state);
+    };
+    let mut value = serde_json::to_value(&state)?;
+    let overrides = env_overrides(&prefix, &value);
+    if overrides.is_empty() {
+        return Ok(state);
+    }
+    for env_override in &overrides {
+        debug!(
+            "Config field {} overridden by {}",
+            env_override.pointer, env_override.var
+        );
+        let override_value = fit_to_type::<T>(&value, env_override);
+        json_value::set(&mut value, &env_override.pointer, override_value);
+    }
+    serde_json::from_value(value)
+        .map_err(|err| redact_error::<T>(err.into()))
+        .wrap_err_with(|| {
+            format!(
+                "Failed to apply environment overrides {}",
+                overrides
+                    .iter()
+                    .map(|env_override| &env_override.var)
+                    .join(", ")
+            )
+        })
+}
+
+/// The value of an override as it should be applied on top of `value`.
+///
+/// Fields without a value, such as `None`, give no hint of their type, so a value parsed as a
+/// number or boolean is kept as a string instead if only that deserializes.
+pub(crate) fn fit_to_type<T: DeserializeOwned>(value: &Value, env_override: &EnvOverride) -> Value {
+    if env_override.value.is_string() {
+        return env_override.value.clone();
+    }
+    let deserializes = |candidate: &Value| {
+        let mut value = value.clone();
+        json_value::set(&mut value, &env_override.pointer, candidate.clone());
+        serde_json::from_value::<T>(value).is_ok()
+    };
+    let raw = Value::String(env_override.raw.clone());
+    if !deserializes(&env_override.value) && deserializes(&raw) {
+        return raw;
+    }
+    env_override.value.clone()
+}
+
+/// Put back the file's own values for fields still holding their environment override,
+/// so saving does not persist values that only came from the environment.
+/// The `location` is where the config is stored, see [`StorageBackend::location`](crate::storage::StorageBackend::location).
+pub(crate) fn strip_env_overrides<T: PersistableState>(
+    key: &PersistenceKey,
+    location: &Path,
+    value: &mut Value,
+) -> eyre::Result<()> {
+    let Some(prefix) = T::env_prefix(key) else {
+        return Ok(());
+    };
+    let overrides = env_overrides(&prefix, value);
+    if overrides.is_empty() {
+        return Ok(());
+    }
+    let original = match last_observed(location) {
+        Some(observed) => observed.snapshot,
+        None => serde_json::to_value(T::default())?,
+    };
+    for env_override in overrides {
+        if value.pointer(&env_override.pointer) != Some(&env_override.value) {
+            continue;
+        }
+        match original.pointer(&env_override.pointer) {
+            Some(original) => json_value::set(value, &env_override.pointer, original.clone()),
+            None => {
+                json_value::remove(value, &env_override.pointer);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn pointer_for(fields: &str, template: &Value) -> Option<String> {
+    let mut pointer = String::new();
+    let mut current = Some(template);
+    for field in fields.split(SEPARATOR) {
+        if field.is_empty() {
+            return None;
+        }
+        let known = current
+            .and_then(Value::as_object)
+            .and_then(|object| object.keys().find(|key| key.eq_ignore_ascii_case(field)));
+        let name = known.cloned().unwrap_or_else(|| field.to_ascii_lowercase());
+        current = current.and_then(|value| value.get(&name));
+        pointer.push('/');
+        pointer.push_str(&escape(&name));
+    }
+    Some(pointer)
+}
+
+/// Parse the value of a variable, keeping it as a string if the field it overrides holds one.
+fn parse_value(raw: &str, current: Option<&Value>) -> Value {
+    if current.is_some_and(Value::is_string) {
+        return Value::String(raw.to_string());
+    }
+    match serde_json::from_str::<Value>(raw) {
+        Ok(value) if !value.is_object() && !value.is_array() => value,
+        _ => Value::String(raw.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+    struct Settings {
+        name: String,
+        port: u16,
+        nickname: Option<String>,
+        limit: Option<u32>,
+    }
+
+    #[async_trait::async_trait]
+    impl PersistableState for Settings {
+        async fn key() -> eyre::Result<PersistenceKey> {
+            Self::key_blocking()
+        }
+
+        fn key_blocking() -> eyre::Result<PersistenceKey> {
+            Ok(PersistenceKey::new("eye_config_env_tests", "settings.json"))
+        }
+    }
+
+    /// Set overrides for a key only used by one test, so tests running in parallel do not see them.
+    fn set_overrides(key: &PersistenceKey, vars: &[(&str, &str)]) {
+        let prefix = Settings::env_prefix(key).expect("the default prefix is set");
+        for (field, value) in vars {
+            // SAFETY: std serializes its own environment access, and no other test uses these variables.
+            unsafe { std::env::set_var(format!("{prefix}{SEPARATOR}{field}"), value) };
+        }
+    }
+
+    #[test]
+    fn types_values_by_the_field_they_override() -> eyre::Result<()> {
+        let key = PersistenceKey::new("eye_config_env_tests", "typing.json");
+        set_overrides(
+            &key,
+            &[
+                ("NAME", "123"),
+                ("PORT", "8081"),
+                ("NICKNAME", "42"),
+                ("LIMIT", "7"),
+            ],
+        );
+
+        let state = apply_env_overrides(&key, Settings::default())?;
+
+        assert_eq!(
+            state,
+            Settings {
+                name: "123".to_owned(),
+                port: 8081,
+                nickname: Some("42".to_owned()),
+                limit: Some(7),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn strips_overridden_values_before_saving() -> eyre::Result<()> {
+        let key = PersistenceKey::new("eye_config_env_tests", "stripping.json");
+        set_overrides(&key, &[("PORT", "8081"), ("NICKNAME", "from env")]);
+        let state = apply_env_overrides(&key, Settings::default())?;
+        let mut value = serde_json::to_value(Settings {
+            name: "changed".to_owned(),
+            nickname: Some("changed too".to_owned()),
+            ..state
+        })?;
+
+        // Never observed, so the defaults stand in for the file's own values.
+        strip_env_overrides::<Settings>(&key, Path::new("memory://env-tests"), &mut value)?;
+
+        let stripped = serde_json::from_value::<Settings>(value)?;
+        assert_eq!(stripped.name, "changed");
+        assert_eq!(stripped.port, 0);
+        assert_eq!(stripped.nickname.as_deref(), Some("changed too"));
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use eyre::Context;
+use eyre::OptionExt;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::path::PathBuf;
+use tracing::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Many readers may hold the lock at once.
+    Shared,
+    /// A single writer holds the lock, excluding readers and other writers.
+    Exclusive,
+}
+
+/// An advisory lock coordinating access to a config file across processes, released when dropped.
+///
+/// The lock is taken on a sibling `.lock` file rather than the config itself,
+/// since atomic saves replace the config file and would orphan a lock held on it.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Block until the lock for `target` can be acquired in the given mode.
+    ///
+    /// Reading must not need write access, so a shared lock is skipped, returning `None`,
+    /// when `target` does not exist or its lock file cannot be opened.
+    pub fn acquire(target: &Path, mode: LockMode) -> eyre::Result<Option<Self>> {
+        let path = lock_path_for(target)?;
+        let file = match mode {
+            LockMode::Shared => {
+                if !target.try_exists()? {
+                    return Ok(None);
+                }
+                // The lock file may exist already even when it cannot be created.
+                match open_lock_file(&path).or_else(|_| File::open(&path)) {
+                    Ok(file) => file,
+                    Err(err) => {
+                        debug!(
+                            "Reading {} without a lock, the lock file cannot be opened: {err}",
+                            target.display()
+                        );
+                        return Ok(None);
+                    }
+                }
+            }
+            LockMode::Exclusive => (|| {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                open_lock_file(&path)
+            })()
+            .wrap_err_with(|| format!("Failed to create the lock file {}", path.display()))?,
+        };
+        let result = match mode {
+            LockMode::Shared => file.lock_shared(),
+            LockMode::Exclusive => file.lock(),
+        };
+        result.wrap_err_with(|| {
+            format!("Failed to acquire {mode:?} lock for {}", target.display())
+        })?;
+        Ok(Some(FileLock { file, path }))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock anyway, unlocking explicitly just makes it prompt.
+        let _ = self.file.unlock();
+    }
+}
+
+fn open_lock_file(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+}
+
+fn lock_path_for(target: &Path) -> eyre::Result<PathBuf> {
+    let file_name = target
+        .file_name()
+        .ok_or_eyre("Cannot lock a path without a file name")?;
+    Ok(target.with_file_name(format!(".{}.lock", file_name.to_string_lossy())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_lock_on_missing_config_creates_nothing() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = dir.path().join("missing").join("settings.json");
+
+        assert!(FileLock::acquire(&target, LockMode::Shared)?.is_none());
+        assert!(!dir.path().join("missing").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn shared_locks_on_existing_config_are_held_together() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = dir.path().join("settings.json");
+        std::fs::write(&target, "{}")?;
+
+        let first = FileLock::acquire(&target, LockMode::Shared)?.expect("the config exists");
+        let second = FileLock::acquire(&target, LockMode::Shared)?.expect("the config exists");
+        assert_eq!(first.path(), dir.path().join(".settings.json.lock"));
+        assert_eq!(first.path(), second.path());
+        Ok(())
+    }
+
+    #[test]
+    fn exclusive_lock_creates_the_config_dir() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = dir.path().join("project").join("settings.json");
+
+        let lock = FileLock::acquire(&target, LockMode::Exclusive)?
+            .expect("exclusive locks are never skipped");
+        assert!(lock.path().exists());
+        Ok(())
+    }
+}
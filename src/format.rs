@@ -0,0 +1,116 @@
+use eyre::Context;
+use eyre::eyre;
+use serde::Deserialize;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::path::Path;
+
+/// The on-disk representation of a persisted config.
+///
+/// JSON is always supported, the other formats need the cargo feature of the same name.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Json5,
+    Toml,
+    Yaml,
+    Ron,
+}
+
+impl Format {
+    /// Pick a format from the extension of a file slug, falling back to JSON.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json5") => Format::Json5,
+            Some("toml") => Format::Toml,
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("ron") => Format::Ron,
+            _ => Format::Json,
+        }
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(&self, content: &str) -> eyre::Result<T> {
+        let value = match self {
+            Format::Json => serde_json::from_str(content)?,
+            #[cfg(feature = "json5")]
+            Format::Json5 => json5::from_str(content)?,
+            #[cfg(feature = "toml")]
+            Format::Toml => toml::from_str(content)?,
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml_ng::from_str(content)?,
+            #[cfg(feature = "ron")]
+            Format::Ron => ron::from_str(content)?,
+            #[allow(unreachable_patterns)]
+            unsupported => return Err(unsupported.missing_feature()),
+        };
+        Ok(value)
+    }
+
+    pub fn serialize<T: Serialize>(&self, value: &T) -> eyre::Result<String> {
+        let content = match self {
+            Format::Json => serde_json::to_string_pretty(value)?,
+            #[cfg(feature = "json5")]
+            Format::Json5 => json5::to_string(value)?,
+            #[cfg(feature = "toml")]
+            Format::Toml => toml::to_string_pretty(value)?,
+            #[cfg(feature = "yaml")]
+            Format::Yaml => serde_yaml_ng::to_string(value)?,
+            #[cfg(feature = "ron")]
+            Format::Ron => ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?,
+            #[allow(unreachable_patterns)]
+            unsupported => return Err(unsupported.missing_feature()),
+        };
+        Ok(content)
+    }
+
+    /// Serialize an untyped value, dropping nulls where the format has no way to represent them.
+    pub fn serialize_value(&self, value: &serde_json::Value) -> eyre::Result<String> {
+        match self {
+            Format::Toml => self.serialize(&strip_nulls(value.clone())),
+            _ => self.serialize(value),
+        }
+    }
+
+    /// The error for a format whose cargo feature is disabled, the feature is named after the format.
+    fn missing_feature(&self) -> eyre::Report {
+        eyre!("Reading and writing {self} requires the `{self}` feature of eye_config")
+    }
+
+    /// Parse content into a format-independent value, used by the CLI to display configs it has no type for.
+    pub fn to_json_value(&self, content: &str) -> eyre::Result<serde_json::Value> {
+        self.deserialize(content)
+            .wrap_err_with(|| format!("Failed to parse content as {self}"))
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Format::Json => "json",
+            Format::Json5 => "json5",
+            Format::Toml => "toml",
+            Format::Yaml => "yaml",
+            Format::Ron => "ron",
+        })
+    }
+}
+
+fn strip_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => object
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(key, value)| (key, strip_nulls(value)))
+            .collect(),
+        serde_json::Value::Array(items) => items.into_iter().map(strip_nulls).collect(),
+        other => other,
+    }
+}
@@ -0,0 +1,149 @@
+//! Helpers for working with configs as untyped JSON values, addressed by JSON pointers.
+
+use serde_json::Map;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+/// Deep merge `overlay` onto `base`, objects are merged key by key and anything else is replaced.
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Every leaf in the value along with its JSON pointer, arrays are treated as leaves.
+pub fn leaves(value: &Value) -> Vec<(String, &Value)> {
+    let mut found = Vec::new();
+    collect_leaves(value, String::new(), &mut found);
+    found
+}
+
+fn collect_leaves<'a>(value: &'a Value, pointer: String, found: &mut Vec<(String, &'a Value)>) {
+    match value {
+        Value::Object(object) if !object.is_empty() => {
+            for (key, value) in object {
+                collect_leaves(value, format!("{pointer}/{}", escape(key)), found);
+            }
+        }
+        _ => found.push((pointer, value)),
+    }
+}
+
+/// Set the value at a JSON pointer, creating intermediate objects as needed.
+pub fn set(root: &mut Value, pointer: &str, value: Value) {
+    let mut current = root;
+    for segment in segments(pointer) {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+        current = current
+            .as_object_mut()
+            .expect("value was just made an object")
+            .entry(segment)
+            .or_insert(Value::Null);
+    }
+    *current = value;
+}
+
+/// Remove the value at a JSON pointer, returning it if it was present.
+pub fn remove(root: &mut Value, pointer: &str) -> Option<Value> {
+    let (parent, last) = pointer.rsplit_once('/')?;
+    match root.pointer_mut(parent)? {
+        Value::Object(object) => object.shift_remove(&unescape(last)),
+        Value::Array(items) => {
+            let index = last
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index < items.len())?;
+            Some(items.remove(index))
+        }
+        _ => None,
+    }
+}
+
+/// The JSON pointer of the parent of a value, or `None` for the root.
+pub fn parent(pointer: &str) -> Option<&str> {
+    pointer.rsplit_once('/').map(|(parent, _)| parent)
+}
+
+/// The unescaped segments of a JSON pointer.
+pub fn segments(pointer: &str) -> impl Iterator<Item = String> + '_ {
+    pointer.split('/').skip(1).map(unescape)
+}
+
+pub fn escape(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+pub fn unescape(segment: &str) -> String {
+    segment.replace("~1", "/").replace("~0", "~")
+}
+
+/// A leaf that differs between two values, see [`diff`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    Added {
+        pointer: String,
+        value: Value,
+    },
+    Removed {
+        pointer: String,
+        value: Value,
+    },
+    Changed {
+        pointer: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::Added { pointer, value } => write!(f, "+ {pointer}: {value}"),
+            Difference::Removed { pointer, value } => write!(f, "- {pointer}: {value}"),
+            Difference::Changed { pointer, old, new } => write!(f, "~ {pointer}: {old} -> {new}"),
+        }
+    }
+}
+
+/// The leaves which were added, removed or changed going from `old` to `new`, ordered by pointer.
+pub fn diff(old: &Value, new: &Value) -> Vec<Difference> {
+    let old = leaves(old).into_iter().collect::<BTreeMap<_, _>>();
+    let new = leaves(new).into_iter().collect::<BTreeMap<_, _>>();
+    let pointers = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+    pointers
+        .into_iter()
+        .filter_map(|pointer| {
+            let pointer = pointer.clone();
+            match (old.get(&pointer), new.get(&pointer)) {
+                (Some(old), Some(new)) if old == new => None,
+                (Some(old), Some(new)) => Some(Difference::Changed {
+                    pointer,
+                    old: (*old).clone(),
+                    new: (*new).clone(),
+                }),
+                (Some(old), None) => Some(Difference::Removed {
+                    pointer,
+                    value: (*old).clone(),
+                }),
+                (None, Some(new)) => Some(Difference::Added {
+                    pointer,
+                    value: (*new).clone(),
+                }),
+                (None, None) => None,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,309 @@
+use crate::changes::ChangeOrigin;
+use crate::changes::publish;
+use crate::cli::config::known_projects::KnownProjects;
+use crate::env_overrides::env_overrides;
+use crate::env_overrides::fit_to_type;
+use crate::file_lock::LockMode;
+use crate::json_value;
+use crate::migrations::take_version;
+use crate::persistable_state::PersistableState;
+use crate::persistable_state::write_value;
+use crate::persistence_key::PersistenceKey;
+use crate::redact::redact_error;
+use crate::schema::take_schema_reference;
+use crate::storage::blocking;
+use eyre::Context;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::path::PathBuf;
+use tracing::debug;
+
+/// A source of configuration values, later layers take precedence over earlier ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layer {
+    /// The value from `Default::default()`.
+    Default,
+    /// A system-wide file such as `/etc/<project>/<slug>`.
+    System,
+    /// The per-user config from the type's [`StorageBackend`](crate::storage::StorageBackend), the only layer that is saved.
+    User,
+    /// A file local to the project being worked on, such as `./.<project>/<slug>`.
+    Project,
+    /// Environment variables named `{prefix}__{field}`.
+    Env,
+    /// Values set explicitly by the application, such as CLI arguments.
+    Override,
+}
+
+/// Loads a config by merging every [`Layer`] in order.
+#[derive(Debug)]
+pub struct LayeredLoader<T> {
+    key: PersistenceKey,
+    system_dir: Option<PathBuf>,
+    project_dir: Option<PathBuf>,
+    env_prefix: Option<String>,
+    overrides: Vec<(String, Value)>,
+    _state: PhantomData<T>,
+}
+
+impl<T: PersistableState> LayeredLoader<T> {
+    /// Use the default system and project directories and environment prefix, with no overrides.
+    pub fn new(key: PersistenceKey) -> Self {
+        Self {
+            system_dir: default_system_dir(&key.project_name),
+            project_dir: std::env::current_dir()
+                .ok()
+                .map(|dir| dir.join(format!(".{}", key.project_name.display()))),
+            env_prefix: T::env_prefix(&key),
+            overrides: Vec::new(),
+            key,
+            _state: PhantomData,
+        }
+    }
+
+    /// The directory holding the system-wide file, or `None` to skip the layer.
+    pub fn system_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.system_dir = dir;
+        self
+    }
+
+    /// The directory holding the project-local file, or `None` to skip the layer.
+    pub fn project_dir(mut self, dir: Option<PathBuf>) -> Self {
+        self.project_dir = dir;
+        self
+    }
+
+    /// Read overrides from environment variables starting with `{prefix}__`, or `None` to skip the layer.
+    pub fn env_prefix(mut self, prefix: Option<String>) -> Self {
+        self.env_prefix = prefix;
+        self
+    }
+
+    /// Override the field at a JSON pointer, such as `/server/port`.
+    pub fn set(mut self, pointer: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.overrides.push((pointer.into(), value.into()));
+        self
+    }
+
+    pub async fn load(self) -> eyre::Result<Layered<T>> {
+        let mut builder = LayerStack::new(serde_json::to_value(T::default())?);
+
+        if let Some(dir) = &self.system_dir
+            && let Some(value) = read_layer::<T>(&self.key, &dir.join(&self.key.file_slug)).await?
+        {
+            builder.push(Layer::System, value);
+        }
+        builder.below_user = builder.value.clone();
+
+        let storage = T::storage();
+        let user = blocking({
+            let key = self.key.clone();
+            move || {
+                let _lock = storage.lock(&key, LockMode::Shared)?;
+                let Some(content) = storage.read(&key)? else {
+                    return Ok(None);
+                };
+                parse_layer::<T>(&key, &content, &storage.location(&key)?).map(Some)
+            }
+        })
+        .await?;
+        if let Some(user) = &user {
+            builder.push(Layer::User, user.clone());
+        }
+        let user = user.unwrap_or_else(|| Value::Object(Default::default()));
+
+        if let Some(dir) = &self.project_dir
+            && let Some(value) = read_layer::<T>(&self.key, &dir.join(&self.key.file_slug)).await?
+        {
+            builder.push(Layer::Project, value);
+        }
+
+        if let Some(prefix) = &self.env_prefix {
+            for env_override in env_overrides(prefix, &builder.value) {
+                debug!(
+                    "Config field {} overridden by {}",
+                    env_override.pointer, env_override.var
+                );
+                let value = fit_to_type::<T>(&builder.value, &env_override);
+                builder.push_at(Layer::Env, &env_override.pointer, value);
+            }
+        }
+
+        for (pointer, value) in self.overrides {
+            builder.push_at(Layer::Override, &pointer, value);
+        }
+
+        let value = serde_json::from_value::<T>(builder.value.clone())
+            .map_err(|err| redact_error::<T>(err.into()))
+            .wrap_err("Failed to deserialize the merged config layers")?;
+
+        if !T::is_secret() {
+            KnownProjects::track_project_accessed(self.key.clone(), T::format(&self.key)).await?;
+        }
+
+        Ok(Layered {
+            value,
+            sources: builder.sources,
+            key: self.key,
+            user,
+            loaded: builder.value,
+            below_user: builder.below_user,
+        })
+    }
+}
+
+/// A config merged from several layers, remembering which layer each value came from.
+#[derive(Debug, Clone)]
+pub struct Layered<T> {
+    /// The effective config, modify it before calling [`Layered::save`].
+    pub value: T,
+    sources: BTreeMap<String, Layer>,
+    key: PersistenceKey,
+    /// The contents of the user file when loaded.
+    user: Value,
+    /// The effective config when loaded.
+    loaded: Value,
+    /// The merged layers below the user layer.
+    below_user: Value,
+}
+
+impl<T: PersistableState> Layered<T> {
+    /// The layer which provided the value at a JSON pointer, such as `/server/port`.
+    ///
+    /// Pointers to objects report the layer of their most recently applied field.
+    pub fn source_of(&self, pointer: &str) -> Option<Layer> {
+        if let Some(layer) = self.sources.get(pointer) {
+            return Some(*layer);
+        }
+        let prefix = format!("{pointer}/");
+        self.sources
+            .iter()
+            .filter(|(leaf, _)| leaf.starts_with(&prefix))
+            .map(|(_, layer)| *layer)
+            .max()
+    }
+
+    /// The layer which provided each leaf of the config, keyed by JSON pointer.
+    pub fn sources(&self) -> &BTreeMap<String, Layer> {
+        &self.sources
+    }
+
+    /// Save the fields changed since loading to the user file.
+    ///
+    /// Values coming from other layers are never written, so a system-wide default
+    /// or environment override does not get baked into the user's config.
+    pub async fn save(&self) -> eyre::Result<()> {
+        self.value
+            .validate()
+            .wrap_err_with(|| format!("Refusing to save invalid config {:?}", self.key))?;
+        let current = serde_json::to_value(&self.value)?;
+        let mut user = self.user.clone();
+        for (pointer, value) in json_value::leaves(&current) {
+            if self.loaded.pointer(&pointer) != Some(value) {
+                json_value::set(&mut user, &pointer, value.clone());
+            }
+        }
+        let mut snapshot = self.below_user.clone();
+        json_value::merge(&mut snapshot, user.clone());
+
+        let key = self.key.clone();
+        let storage = T::storage();
+        let value = self.value.clone();
+        blocking(move || {
+            let _lock = storage.lock(&key, LockMode::Exclusive)?;
+            write_value::<T>(&*storage, &key, user, snapshot)?;
+            publish(&storage.location(&key)?, &value, ChangeOrigin::LocalSave);
+            Ok(())
+        })
+        .await
+    }
+}
+
+struct LayerStack {
+    value: Value,
+    sources: BTreeMap<String, Layer>,
+    below_user: Value,
+}
+
+impl LayerStack {
+    fn new(defaults: Value) -> Self {
+        let sources = json_value::leaves(&defaults)
+            .into_iter()
+            .map(|(pointer, _)| (pointer, Layer::Default))
+            .collect();
+        Self {
+            below_user: defaults.clone(),
+            value: defaults,
+            sources,
+        }
+    }
+
+    fn push(&mut self, layer: Layer, value: Value) {
+        for (pointer, leaf) in json_value::leaves(&value) {
+            // Merging an empty object into an existing one changes nothing.
+            if leaf.as_object().is_some_and(|object| object.is_empty())
+                && self.value.pointer(&pointer).is_some_and(Value::is_object)
+            {
+                continue;
+            }
+            self.mark(pointer, layer);
+        }
+        json_value::merge(&mut self.value, value);
+    }
+
+    fn push_at(&mut self, layer: Layer, pointer: &str, value: Value) {
+        json_value::set(&mut self.value, pointer, value);
+        self.mark(pointer.to_string(), layer);
+    }
+
+    /// Record the layer of a leaf, forgetting the sources of anything it replaced.
+    fn mark(&mut self, pointer: String, layer: Layer) {
+        let descendants = format!("{pointer}/");
+        self.sources.retain(|existing, _| {
+            !existing.starts_with(&descendants) && !pointer.starts_with(&format!("{existing}/"))
+        });
+        self.sources.insert(pointer, layer);
+    }
+}
+
+/// Read a layer file as an untyped value, upgraded to the current version.
+async fn read_layer<T: PersistableState>(
+    key: &PersistenceKey,
+    path: &Path,
+) -> eyre::Result<Option<Value>> {
+    if !tokio::fs::try_exists(path).await? {
+        return Ok(None);
+    }
+    let content = tokio::fs::read(path).await?;
+    parse_layer::<T>(key, &content, path).map(Some)
+}
+
+/// Parse the contents of a layer, `location` is only used in messages.
+fn parse_layer<T: PersistableState>(
+    key: &PersistenceKey,
+    content: &[u8],
+    location: &Path,
+) -> eyre::Result<Value> {
+    debug!("Loading config layer from {}", location.display());
+    let mut value = T::format(key)
+        .deserialize::<Value>(std::str::from_utf8(content)?)
+        .map_err(redact_error::<T>)
+        .wrap_err_with(|| format!("Failed to parse config layer {}", location.display()))?;
+    take_schema_reference(&mut value);
+    let version = take_version(&mut value)?;
+    T::migrations().migrate(value, version)
+}
+
+#[cfg(windows)]
+fn default_system_dir(project_name: &Path) -> Option<PathBuf> {
+    std::env::var_os("ProgramData").map(|dir| PathBuf::from(dir).join(project_name))
+}
+
+#[cfg(not(windows))]
+fn default_system_dir(project_name: &Path) -> Option<PathBuf> {
+    Some(Path::new("/etc").join(project_name))
+}
@@ -1,4 +1,29 @@
+pub mod atomic_write;
+pub mod backups;
+pub mod blocking;
+pub mod changes;
 pub mod cli;
+pub mod config_handle;
+pub mod conflict;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod env_overrides;
+pub mod file_lock;
+pub mod format;
+pub mod json_value;
+pub mod layered;
+pub mod migrations;
 pub mod persistable_state;
 pub mod persistence_key;
+pub mod recovery;
+pub mod redact;
+pub mod schema;
+pub mod storage;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod validation;
+pub mod watch;
 pub use async_trait;
+pub use eyre;
+#[cfg(feature = "schemars")]
+pub use schemars;
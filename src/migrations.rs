@@ -0,0 +1,191 @@
+use eyre::Context;
+use eyre::bail;
+use eyre::eyre;
+use serde_json::Map;
+use serde_json::Value;
+
+/// The top-level field holding the schema version of a persisted config.
+///
+/// Files without the field are treated as version 0.
+pub const VERSION_FIELD: &str = "$version";
+
+type MigrationStep = Box<dyn Fn(Value) -> eyre::Result<Value> + Send + Sync>;
+
+/// An ordered chain of upgrades applied to the raw config before it is deserialized.
+///
+/// The step at index `n` upgrades a file from version `n` to version `n + 1`,
+/// so the current version of a config is the number of steps.
+#[derive(Default)]
+pub struct Migrations {
+    steps: Vec<MigrationStep>,
+}
+
+impl Migrations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the step which upgrades from the current version to the next one.
+    pub fn then(
+        mut self,
+        step: impl Fn(Value) -> eyre::Result<Value> + Send + Sync + 'static,
+    ) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    pub fn current_version(&self) -> u64 {
+        self.steps.len() as u64
+    }
+
+    /// Run every step needed to bring a value written at `from` up to the current version.
+    pub fn migrate(&self, mut value: Value, from: u64) -> eyre::Result<Value> {
+        let current = self.current_version();
+        if from > current {
+            bail!(
+                "Config has version {from} which is newer than the latest known version {current}"
+            );
+        }
+        for (version, step) in self.steps.iter().enumerate().skip(from as usize) {
+            value = step(value).wrap_err_with(|| {
+                format!(
+                    "Failed to migrate config from version {version} to {}",
+                    version + 1
+                )
+            })?;
+        }
+        Ok(value)
+    }
+}
+
+impl std::fmt::Debug for Migrations {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Migrations")
+            .field("current_version", &self.current_version())
+            .finish()
+    }
+}
+
+/// Remove the version field from a loaded value, returning the version the file was written at.
+pub fn take_version(value: &mut Value) -> eyre::Result<u64> {
+    let Some(object) = value.as_object_mut() else {
+        return Ok(0);
+    };
+    match object.shift_remove(VERSION_FIELD) {
+        None => Ok(0),
+        Some(version) => version.as_u64().ok_or_else(|| {
+            eyre!("Expected {VERSION_FIELD} to be a non-negative integer, found {version}")
+        }),
+    }
+}
+
+/// Stamp a value with its version before it is written, leaving unversioned configs untouched.
+pub fn with_version(value: Value, version: u64) -> Value {
+    match value {
+        Value::Object(object) if version > 0 => {
+            let mut versioned = Map::with_capacity(object.len() + 1);
+            versioned.insert(VERSION_FIELD.to_string(), Value::from(version));
+            versioned.extend(object);
+            Value::Object(versioned)
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistable_state::PersistableState;
+    use crate::persistable_state::load_state;
+    use crate::persistence_key::PersistenceKey;
+    use crate::storage::FilesystemBackend;
+    use crate::storage::StorageBackend;
+    use serde::Deserialize;
+    use serde::Serialize;
+    use serde_json::json;
+
+    #[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+    struct Settings {
+        display_name: String,
+        theme: String,
+    }
+
+    #[async_trait::async_trait]
+    impl PersistableState for Settings {
+        async fn key() -> eyre::Result<PersistenceKey> {
+            Self::key_blocking()
+        }
+
+        fn key_blocking() -> eyre::Result<PersistenceKey> {
+            Ok(PersistenceKey::new(
+                "eye_config_migration_tests",
+                "settings.json",
+            ))
+        }
+
+        fn migrations() -> Migrations {
+            Migrations::new()
+                // Version 1 renamed `name` to `display_name`.
+                .then(|mut value| {
+                    if let Some(name) = value
+                        .as_object_mut()
+                        .and_then(|object| object.shift_remove("name"))
+                    {
+                        value["display_name"] = name;
+                    }
+                    Ok(value)
+                })
+                // Version 2 added `theme`.
+                .then(|mut value| {
+                    value["theme"] = json!("light");
+                    Ok(value)
+                })
+        }
+    }
+
+    #[test]
+    fn migrates_from_the_version_of_the_file() -> eyre::Result<()> {
+        let migrations = Settings::migrations();
+
+        assert_eq!(
+            migrations.migrate(json!({ "name": "Ada" }), 0)?,
+            json!({ "display_name": "Ada", "theme": "light" })
+        );
+        assert_eq!(
+            migrations.migrate(json!({ "name": "kept" }), 1)?,
+            json!({ "name": "kept", "theme": "light" })
+        );
+        assert!(migrations.migrate(json!({}), 3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn loading_writes_back_the_upgraded_file() -> eyre::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let storage = FilesystemBackend::in_dir(dir.path());
+        let key = Settings::key_blocking()?;
+        storage.write(&key, br#"{"name": "Ada"}"#)?;
+
+        let loaded = load_state::<Settings>(&storage, &key)?;
+
+        assert_eq!(
+            loaded.state,
+            Settings {
+                display_name: "Ada".to_owned(),
+                theme: "light".to_owned(),
+            }
+        );
+        let written = serde_json::from_slice::<Value>(&storage.read(&key)?.unwrap_or_default())?;
+        assert_eq!(
+            written,
+            json!({ VERSION_FIELD: 2, "display_name": "Ada", "theme": "light" })
+        );
+        let backups = storage.list_backups(&key)?;
+        assert_eq!(backups.len(), 1);
+        assert_eq!(
+            std::fs::read_to_string(&backups[0].path)?,
+            r#"{"name": "Ada"}"#
+        );
+        Ok(())
+    }
+}
@@ -1,15 +1,60 @@
+use crate::backups::BackupRetention;
+use crate::backups::default_backup_retention;
+use crate::changes::ChangeOrigin;
+use crate::changes::ChangeSubscription;
+use crate::changes::publish;
+use crate::changes::subscribe;
 use crate::cli::config::known_projects::KnownProjects;
+use crate::conflict::ConflictError;
+use crate::conflict::ConflictPolicy;
+use crate::conflict::Fingerprint;
+use crate::conflict::Observed;
+use crate::conflict::last_observed;
+use crate::conflict::reapply;
+use crate::conflict::record_observed;
+use crate::env_overrides::apply_env_overrides;
+use crate::env_overrides::strip_env_overrides;
+use crate::file_lock::LockMode;
+use crate::format::Format;
+use crate::layered::LayeredLoader;
+use crate::migrations::Migrations;
+use crate::migrations::take_version;
+use crate::migrations::with_version;
 use crate::persistence_key::PersistenceKey;
-use chrono::Utc;
+use crate::recovery::Loaded;
+use crate::recovery::PromptChoice;
+use crate::recovery::Recovery;
+use crate::recovery::RecoveryContext;
+use crate::recovery::RecoveryPolicy;
+use crate::recovery::recover_lenient;
+use crate::redact::describe_error;
+use crate::redact::redact_error;
+#[cfg(feature = "schemars")]
+use crate::schema::SchemaReference;
+use crate::schema::take_schema_reference;
+#[cfg(feature = "schemars")]
+use crate::schema::with_schema_reference;
+#[cfg(feature = "schemars")]
+use crate::schema::write_sidecar;
+use crate::storage::StorageBackend;
+use crate::storage::blocking;
+use crate::storage::storage_backend;
+use crate::validation::ValidationErrors;
+use crate::watch::StateWatcher;
 use eyre::Context;
 use eyre::Result;
 use serde::Deserialize;
 use serde::Serialize;
-use serde_json::{self};
-use tokio::fs;
+use std::path::Path;
+use std::sync::Arc;
 use tracing::debug;
+use tracing::info;
 use tracing::warn;
 
+/// Derive [`PersistableState`] for a type with a constant key, see [`eye_config_derive::PersistableState`].
+#[cfg(feature = "derive")]
+pub use eye_config_derive::PersistableState;
+
 #[async_trait::async_trait]
 pub trait PersistableState:
     Sized
@@ -25,81 +70,686 @@ pub trait PersistableState:
 {
     async fn key() -> eyre::Result<PersistenceKey>;
 
+    /// The key used by the [`BlockingPersistableState`](crate::blocking::BlockingPersistableState) API.
+    /// By default, [`key`](PersistableState::key) is run to completion on the current thread,
+    /// override this if it needs a tokio runtime.
+    fn key_blocking() -> eyre::Result<PersistenceKey> {
+        futures_executor::block_on(Self::key())
+    }
+
     /// Asynchronously load the configuration with incremental upgrading.
+    ///
+    /// Fields can be overridden by environment variables, see [`env_prefix`](PersistableState::env_prefix).
+    ///
+    /// The file is read under a shared lock, so it is never observed while another process is writing it.
     async fn load() -> Result<Self> {
-        let key = Self::key().await?;
-        let exists = key.exists().await?;
-        let path = key.file_path()?;
-        let instance = if exists {
-            debug!("Loading config from {}", path.display());
-            let content = fs::read_to_string(&path).await?;
-
-            // Try to deserialize the string directly into the config type.
-            match serde_json::from_str::<Self>(&content) {
-                Ok(config) => config,
-                Err(err) => {
-                    warn!(
-                        "Failed to load config as valid type, will make a backup and revert to defaults. Error: {}",
-                        err
-                    );
-                    // Backup the original file and use the default.
-                    let now = Utc::now().format("%Y%m%dT%H%M%SZ");
-                    let backup_path = path.with_extension(format!("{now}.bak"));
-                    fs::copy(&path, &backup_path).await?;
-
-                    // Inform the user about the backup.
-                    warn!(
-                        "Backup of the original config created at {}",
-                        backup_path.display()
-                    );
+        Ok(Self::load_with_report().await?.state)
+    }
 
-                    Self::default()
-                }
-            }
-        } else {
-            debug!(
-                "Config file {} does not exist, using default config",
-                path.display()
-            );
-            Self::default()
-        };
+    /// Load the configuration, reporting which recovery ran if the file was invalid.
+    async fn load_with_report() -> Result<Loaded<Self>> {
+        let key = Self::key().await?;
+        let storage = Self::storage();
+        let loaded = blocking({
+            let key = key.clone();
+            move || load_state::<Self>(&*storage, &key)
+        })
+        .await?;
 
         if !Self::is_secret() {
-            KnownProjects::track_project_accessed(key).await?;
+            let format = Self::format(&key);
+            KnownProjects::track_project_accessed(key, format).await?;
         }
 
-        Ok(instance)
+        Ok(loaded)
     }
 
     /// Asynchronously save the configuration.
+    ///
+    /// The file is replaced atomically, so an interrupted save leaves the previous contents intact.
+    ///
+    /// If the file changed on disk since this process last read or wrote it,
+    /// the [`conflict_policy`](PersistableState::conflict_policy) decides what happens.
+    /// Changes saved from another copy loaded in this process are not conflicts, see the policy.
     async fn save(&self) -> Result<()> {
-        let path = Self::key().await?.file_path()?;
-        if let Some(dir) = path.parent() {
-            fs::create_dir_all(dir).await?;
-        }
-        let content = serde_json::to_string_pretty(self).wrap_err_with(|| {
-            eyre::eyre!(
-                "Failed to serialize config {} with value {self:?}",
-                path.display()
-            )
-        })?;
-        debug!("Writing config to {:?}", path);
-        fs::write(&path, content).await?;
+        let key = Self::key().await?;
+        let storage = Self::storage();
+        let state = self.clone();
+        blocking(move || save_state(&*storage, &key, &state)).await?;
         Ok(())
     }
 
+    /// Load, modify and save the configuration while holding an exclusive lock,
+    /// so updates made by other processes in the meantime are not clobbered.
+    async fn update<F>(f: F) -> Result<Self>
+    where
+        F: FnOnce(&mut Self) + Send,
+    {
+        let key = Self::key().await?;
+        let storage = Self::storage();
+        let (lock, mut read) = blocking({
+            let key = key.clone();
+            let storage = storage.clone();
+            move || {
+                let lock = storage.lock(&key, LockMode::Exclusive)?;
+                let read = read_state::<Self>(&*storage, &key)?;
+                finish_migration(&*storage, &key, &read)?;
+                Ok((lock, read))
+            }
+        })
+        .await?;
+        // `f` may borrow from the caller, so it runs here between the blocking read and write.
+        f(&mut read.state);
+        let instance = blocking({
+            let key = key.clone();
+            move || {
+                write_state(&*storage, &key, &read.state, ChangeOrigin::LocalSave)?;
+                drop(lock);
+                Ok(read.state)
+            }
+        })
+        .await?;
+
+        if !Self::is_secret() {
+            let format = Self::format(&key);
+            KnownProjects::track_project_accessed(key, format).await?;
+        }
+
+        Ok(instance)
+    }
+
+    /// Back up the stored config and replace it with defaults, publishing a [`ChangeOrigin::Reset`].
+    async fn reset() -> Result<Self> {
+        let key = Self::key().await?;
+        let storage = Self::storage();
+        blocking(move || reset_state::<Self>(&*storage, &key)).await
+    }
+
+    /// Receive the changes made to the config within this process, from any save, load, reset or
+    /// [`watch`](PersistableState::watch)er that notices a change. Use [`ChangeSubscription::filter`]
+    /// to only receive changes to some fields.
+    async fn subscribe() -> Result<ChangeSubscription<Self>> {
+        let key = Self::key().await?;
+        let location = Self::storage().location(&key)?;
+        subscribe::<Self>(&key, &location)
+    }
+
+    /// Watch the config file, receiving the new config whenever another process changes it.
+    ///
+    /// Saves made by this process are not reported.
+    async fn watch() -> Result<StateWatcher<Self>> {
+        StateWatcher::new(Self::key().await?).await
+    }
+
+    /// Load the configuration merged from every [`Layer`](crate::layered::Layer),
+    /// see [`LayeredLoader`] for the sources and how to configure them.
+    async fn layered() -> Result<LayeredLoader<Self>> {
+        Ok(LayeredLoader::new(Self::key().await?))
+    }
+
+    /// Modify and save the configuration, `self` keeps its previous value if the save fails.
     async fn modify_and_save<F>(&mut self, f: F) -> Result<()>
     where
         F: FnOnce(&mut Self) + Send,
     {
-        f(self);
-        self.save().await?;
+        self.try_modify_and_save(|state| {
+            f(state);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Modify and save the configuration with a change which can fail, such as one validating its input.
+    /// Nothing is written if `f` fails, and `self` keeps its previous value if either `f` or the save fails.
+    async fn try_modify_and_save<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()> + Send,
+    {
+        let mut modified = self.clone();
+        f(&mut modified)?;
+        *self = save_adopting(modified).await?;
+        Ok(())
+    }
+
+    /// Like [`try_modify_and_save`](PersistableState::try_modify_and_save) for changes which await,
+    /// `f` is given a copy of the configuration and returns the modified copy.
+    ///
+    /// ```ignore
+    /// config
+    ///     .try_modify_and_save_async(|mut config| async move {
+    ///         config.token = refresh_token(&config.token).await?;
+    ///         Ok(config)
+    ///     })
+    ///     .await?;
+    /// ```
+    async fn try_modify_and_save_async<F, Fut>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(Self) -> Fut + Send,
+        Fut: Future<Output = Result<Self>> + Send,
+    {
+        let modified = f(self.clone()).await?;
+        *self = save_adopting(modified).await?;
         Ok(())
     }
 
+    /// The format used to read and write the config file.
+    /// By default, the format is picked from the extension of the key's file slug.
+    fn format(key: &PersistenceKey) -> Format {
+        key.format()
+    }
+
+    /// Upgrades applied to older files before they are deserialized.
+    /// By default, configs are unversioned and have no migrations.
+    fn migrations() -> Migrations {
+        Migrations::new()
+    }
+
+    /// What `save` does when the file was modified by someone else since it was loaded.
+    /// By default, the save fails with a [`ConflictError`](crate::conflict::ConflictError).
+    ///
+    /// The file is compared with the version this process last read or wrote, not with the version
+    /// each loaded value came from. After `a = load()`, `b = load()` and `b.save()`, saving `a`
+    /// overwrites the changes made to `b` without a conflict. Use [`update`](PersistableState::update)
+    /// or a shared [`ConfigHandle`](crate::config_handle::ConfigHandle) when several parts of a
+    /// process modify the same config.
+    fn conflict_policy() -> ConflictPolicy {
+        ConflictPolicy::Fail
+    }
+
+    /// The prefix of environment variables overriding individual fields,
+    /// with nested fields separated by `__` such as `{prefix}__SERVER__PORT`.
+    /// Values are parsed as JSON scalars, falling back to plain strings.
+    ///
+    /// Overridden values are not written back to the file when saving.
+    /// By default, the prefix is derived from the key, return `None` to disable overrides.
+    fn env_prefix(key: &PersistenceKey) -> Option<String> {
+        Some(key.env_prefix())
+    }
+
+    /// When a file fails to deserialize, keep every field that is still valid
+    /// instead of reverting the whole config to defaults. A backup is made either way.
+    /// This is shorthand for a [`recovery_policy`](PersistableState::recovery_policy) of [`RecoveryPolicy::Lenient`].
+    fn lenient_recovery() -> bool {
+        false
+    }
+
+    /// How `load` handles a file that exists but fails to deserialize or [`validate`](PersistableState::validate).
+    /// By default, the file is backed up and the config reverts to defaults,
+    /// or keeps its valid fields if [`lenient_recovery`](PersistableState::lenient_recovery) is set.
+    fn recovery_policy() -> RecoveryPolicy<Self> {
+        match Self::lenient_recovery() {
+            true => RecoveryPolicy::Lenient,
+            false => RecoveryPolicy::BackupAndDefault,
+        }
+    }
+
+    /// Limits on the `.bak` files kept next to the config, enforced whenever a backup is written.
+    /// By default, the global [`default_backup_retention`] is used.
+    fn backup_retention() -> BackupRetention {
+        default_backup_retention()
+    }
+
+    /// Where the config is stored.
+    /// By default, the global [`storage_backend`] is used, which stores configs as files.
+    fn storage() -> Arc<dyn StorageBackend> {
+        storage_backend()
+    }
+
     /// If a config is secret, it will not be included in the index used by the eye_config cli.
     /// By default, configs are not secret.
     fn is_secret() -> bool {
         false
     }
+
+    /// Check the config for values which deserialize but make no sense, such as a port out of range.
+    /// `save` refuses invalid configs, and `load` handles them according to the [`recovery_policy`](PersistableState::recovery_policy).
+    ///
+    /// Defaults are not validated when loading falls back to them.
+    fn validate(&self) -> std::result::Result<(), ValidationErrors> {
+        Ok(())
+    }
+
+    /// The JSON Schema of the config, for editors and documentation.
+    /// Types deriving `JsonSchema` can return `Some(schemars::schema_for!(Self))`.
+    #[cfg(feature = "schemars")]
+    fn schema() -> Option<schemars::Schema> {
+        None
+    }
+
+    /// How saved files point editors at the [`schema`](PersistableState::schema).
+    /// By default, they do not reference it.
+    #[cfg(feature = "schemars")]
+    fn schema_reference() -> SchemaReference {
+        SchemaReference::None
+    }
+
+    /// The Unix permission bits given to the config file when it is written, instead of relying on the umask.
+    /// By default, secret configs are only accessible to their owner and other configs use the umask.
+    ///
+    /// Loading warns when an existing file is accessible to the group or other users beyond this mode.
+    fn file_mode() -> Option<u32> {
+        Self::is_secret().then_some(0o600)
+    }
+}
+
+/// A config as read from disk, before any migration has been written back.
+pub(crate) struct ReadState<T> {
+    pub state: T,
+    pub migrated_from: Option<u64>,
+    pub recovery: Option<Recovery>,
+}
+
+/// Read the config under a shared lock, writing back any migration under an exclusive lock.
+pub(crate) fn load_state<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+) -> Result<Loaded<T>> {
+    let read = {
+        let _lock = storage.lock(key, LockMode::Shared)?;
+        read_state::<T>(storage, key)?
+    };
+    let read = if read.migrated_from.is_some() {
+        // Upgrading rewrites the file, so read it again under the exclusive lock in case another process got there first.
+        let _lock = storage.lock(key, LockMode::Exclusive)?;
+        let read = read_state::<T>(storage, key)?;
+        finish_migration(storage, key, &read)?;
+        read
+    } else {
+        read
+    };
+    Ok(Loaded {
+        state: read.state,
+        recovery: read.recovery,
+    })
+}
+
+/// Write the config under an exclusive lock, subject to the conflict policy.
+/// Returns the merged state when external changes were reapplied, which the caller should adopt.
+pub(crate) fn save_state<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    state: &T,
+) -> Result<Option<T>> {
+    let _lock = storage.lock(key, LockMode::Exclusive)?;
+    write_checked(storage, key, state)
+}
+
+/// Save `state` and return what was written, so external changes reapplied on save are kept.
+async fn save_adopting<T: PersistableState>(state: T) -> Result<T> {
+    let key = T::key().await?;
+    let storage = T::storage();
+    blocking(move || {
+        let merged = save_state(&*storage, &key, &state)?;
+        Ok(merged.unwrap_or(state))
+    })
+    .await
+}
+
+/// Load, modify and write the config while holding an exclusive lock.
+pub(crate) fn update_state<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    f: impl FnOnce(&mut T),
+) -> Result<T> {
+    let _lock = storage.lock(key, LockMode::Exclusive)?;
+    let mut read = read_state::<T>(storage, key)?;
+    finish_migration(storage, key, &read)?;
+    f(&mut read.state);
+    write_state(storage, key, &read.state, ChangeOrigin::LocalSave)?;
+    Ok(read.state)
+}
+
+/// Back up the config and replace it with defaults while holding an exclusive lock.
+pub(crate) fn reset_state<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+) -> Result<T> {
+    let _lock = storage.lock(key, LockMode::Exclusive)?;
+    if storage.exists(key)? {
+        let backup_path = storage.backup(key, T::backup_retention())?;
+        info!(
+            "Resetting config {} to defaults, the previous version was backed up at {}",
+            storage.location(key)?.display(),
+            backup_path.display()
+        );
+    }
+    let state = apply_env_overrides(key, T::default())?;
+    write_state(storage, key, &state, ChangeOrigin::Reset)?;
+    Ok(state)
+}
+
+/// Read and parse the config file, falling back to defaults when it is missing or invalid.
+/// The caller is responsible for holding the lock.
+fn read_state<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+) -> Result<ReadState<T>> {
+    let path = storage.location(key)?;
+    let (fingerprint, read) = if let Some(content) = storage.read(key)? {
+        debug!("Loading config from {}", path.display());
+        warn_if_exposed::<T>(storage, key, &path)?;
+        let fingerprint = Fingerprint::new(&content);
+        let read = match parse_value::<T>(key, &content) {
+            Ok(mut value) => {
+                let version = take_version(&mut value)?;
+                let migrations = T::migrations();
+                let value = migrations.migrate(value, version)?;
+                match serde_json::from_value::<T>(value.clone()) {
+                    Ok(state) => match state.validate() {
+                        Ok(()) => ReadState {
+                            state,
+                            migrated_from: (version < migrations.current_version())
+                                .then_some(version),
+                            recovery: None,
+                        },
+                        Err(errors) => recover(storage, key, Some(value), errors.into())?,
+                    },
+                    Err(err) => recover(storage, key, Some(value), err.into())?,
+                }
+            }
+            Err(err) => recover(storage, key, None, err)?,
+        };
+        (Some(fingerprint), read)
+    } else {
+        debug!(
+            "Config file {} does not exist, using default config",
+            path.display()
+        );
+        let read = ReadState {
+            state: T::default(),
+            migrated_from: None,
+            recovery: None,
+        };
+        (None, read)
+    };
+    record_observed(
+        &path,
+        Observed {
+            fingerprint,
+            snapshot: serde_json::to_value(&read.state)?,
+        },
+    );
+    let state = apply_env_overrides(key, read.state)?;
+    let origin = match read.recovery {
+        Some(Recovery::Default { .. }) => ChangeOrigin::Reset,
+        _ => ChangeOrigin::ExternalChange,
+    };
+    publish(&path, &state, origin);
+    Ok(ReadState { state, ..read })
+}
+
+/// Warn when the config file is accessible to more users than its declared [`file_mode`](PersistableState::file_mode).
+fn warn_if_exposed<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    path: &Path,
+) -> Result<()> {
+    let (Some(declared), Some(actual)) = (T::file_mode(), storage.mode(key)?) else {
+        return Ok(());
+    };
+    if actual & !declared & 0o077 != 0 {
+        warn!(
+            "Config {} has mode {actual:o} but should be {declared:o}, other users may be able to read it. It will be fixed the next time it is saved, or run `chmod {declared:o} {}`",
+            path.display(),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Parse file contents into an untyped value, before any migrations.
+fn parse_value<T: PersistableState>(
+    key: &PersistenceKey,
+    content: &[u8],
+) -> Result<serde_json::Value> {
+    let content = std::str::from_utf8(content)?;
+    let mut value = T::format(key).deserialize::<serde_json::Value>(content)?;
+    take_schema_reference(&mut value);
+    Ok(value)
+}
+
+/// Deserialize file contents, running any migrations needed to reach the current version.
+pub(crate) fn parse_state<T: PersistableState>(
+    key: &PersistenceKey,
+    content: &[u8],
+) -> Result<ReadState<T>> {
+    let mut value = parse_value::<T>(key, content)?;
+    let version = take_version(&mut value)?;
+    let migrations = T::migrations();
+    let value = migrations.migrate(value, version)?;
+    let state = serde_json::from_value::<T>(value)?;
+    Ok(ReadState {
+        state,
+        migrated_from: (version < migrations.current_version()).then_some(version),
+        recovery: None,
+    })
+}
+
+/// Serialize and atomically write the config file.
+/// The caller is responsible for holding the exclusive lock.
+fn write_state<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    state: &T,
+    origin: ChangeOrigin,
+) -> Result<()> {
+    if let Err(errors) = state.validate() {
+        let location = storage.location(key)?;
+        return Err(eyre::Report::new(errors))
+            .wrap_err_with(|| format!("Refusing to save invalid config {}", location.display()));
+    }
+    let mut value = serde_json::to_value(state)
+        .map_err(|err| redact_error::<T>(err.into()))
+        .wrap_err_with(|| {
+            let location = storage
+                .location(key)
+                .map(|path| path.display().to_string())
+                .unwrap_or_default();
+            // Secret configs are never logged, other configs are printed with their own `Debug`
+            // which can mask sensitive fields, see the `redact` module.
+            match T::is_secret() {
+                true => eyre::eyre!("Failed to serialize config {location}"),
+                false => eyre::eyre!("Failed to serialize config {location} with value {state:?}"),
+            }
+        })?;
+    let path = storage.location(key)?;
+    strip_env_overrides::<T>(key, &path, &mut value)?;
+    write_value::<T>(storage, key, value.clone(), value)?;
+    publish(&path, state, origin);
+    Ok(())
+}
+
+/// Atomically write an untyped config, which may be a partial config such as a single layer.
+/// The `snapshot` is the full config the file represents, recorded for conflict detection.
+/// The caller is responsible for holding the exclusive lock.
+pub(crate) fn write_value<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    value: serde_json::Value,
+    snapshot: serde_json::Value,
+) -> Result<()> {
+    let path = storage.location(key)?;
+    let value = with_version(value, T::migrations().current_version());
+    #[cfg(feature = "schemars")]
+    let value = {
+        write_sidecar::<T>(storage, key)?;
+        with_schema_reference::<T>(key, value)
+    };
+    let content = T::format(key).serialize_value(&value)?;
+    debug!("Writing config to {:?}", path);
+    storage.write_with_mode(key, content.as_bytes(), T::file_mode())?;
+    record_observed(
+        &path,
+        Observed {
+            fingerprint: Some(Fingerprint::new(content.as_bytes())),
+            snapshot,
+        },
+    );
+    Ok(())
+}
+
+/// Write the config unless it was modified externally, in which case the conflict policy applies.
+/// Returns the merged state if external changes were reapplied.
+/// The caller is responsible for holding the exclusive lock.
+fn write_checked<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    state: &T,
+) -> Result<Option<T>> {
+    let path = storage.location(key)?;
+    let Some(observed) = last_observed(&path) else {
+        // Nothing was read in this process, so there is nothing to conflict with.
+        write_state(storage, key, state, ChangeOrigin::LocalSave)?;
+        return Ok(None);
+    };
+    if Fingerprint::read(storage, key)? == observed.fingerprint {
+        write_state(storage, key, state, ChangeOrigin::LocalSave)?;
+        return Ok(None);
+    }
+    match T::conflict_policy() {
+        ConflictPolicy::Fail => Err(ConflictError { path }.into()),
+        ConflictPolicy::Overwrite => {
+            warn!(
+                "Config {} was modified externally, overwriting it",
+                path.display()
+            );
+            write_state(storage, key, state, ChangeOrigin::LocalSave)?;
+            Ok(None)
+        }
+        ConflictPolicy::ReloadAndReapply => {
+            info!(
+                "Config {} was modified externally, reapplying changes on top of it",
+                path.display()
+            );
+            let theirs = read_state::<T>(storage, key)?;
+            let merged = reapply(
+                &observed.snapshot,
+                &serde_json::to_value(state)?,
+                serde_json::to_value(&theirs.state)?,
+            );
+            let merged = serde_json::from_value::<T>(merged)?;
+            write_state(storage, key, &merged, ChangeOrigin::LocalSave)?;
+            Ok(Some(merged))
+        }
+    }
+}
+
+/// Write back a config that was upgraded on read, keeping a backup of the previous version.
+/// The caller is responsible for holding the exclusive lock.
+fn finish_migration<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    read: &ReadState<T>,
+) -> Result<()> {
+    let Some(version) = read.migrated_from else {
+        return Ok(());
+    };
+    let path = storage.location(key)?;
+    let backup_path = storage.backup(key, T::backup_retention())?;
+    info!(
+        "Migrated config {} from version {version} to {}, the previous version was backed up at {}",
+        path.display(),
+        T::migrations().current_version(),
+        backup_path.display()
+    );
+    write_state(storage, key, &read.state, ChangeOrigin::LocalSave)
+}
+
+/// Handle a file that failed to load according to the type's recovery policy.
+///
+/// The `value` is the parsed contents, or `None` when the file could not be parsed at all.
+fn recover<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    value: Option<serde_json::Value>,
+    err: eyre::Report,
+) -> Result<ReadState<T>> {
+    let path = storage.location(key)?;
+    let policy = T::recovery_policy();
+    if let RecoveryPolicy::Error = policy {
+        return Err(
+            redact_error::<T>(err).wrap_err(format!("Config {} is invalid", path.display()))
+        );
+    }
+    warn!(
+        "Failed to load config {} as valid type, will make a backup and recover. Error: {}",
+        path.display(),
+        describe_error::<T>(&err)
+    );
+    let backup_path = storage.backup(key, T::backup_retention())?;
+    // Inform the user about the backup.
+    warn!(
+        "Backup of the original config created at {}",
+        backup_path.display()
+    );
+
+    let lenient = match policy {
+        RecoveryPolicy::Error => unreachable!("handled before making a backup"),
+        RecoveryPolicy::BackupAndDefault => false,
+        RecoveryPolicy::Lenient => true,
+        RecoveryPolicy::Prompt => match PromptChoice::pick(&path, &describe_error::<T>(&err))? {
+            PromptChoice::Lenient => true,
+            PromptChoice::Default => false,
+            PromptChoice::Abort => {
+                return Err(redact_error::<T>(err).wrap_err(format!(
+                    "Loading config {} was aborted, a backup was made at {}",
+                    path.display(),
+                    backup_path.display()
+                )));
+            }
+        },
+        RecoveryPolicy::Custom(callback) => {
+            let state = callback(&RecoveryContext {
+                path: &path,
+                backup_path: &backup_path,
+                value: value.as_ref(),
+                error: &err,
+            })?;
+            if let Err(errors) = state.validate() {
+                return Err(eyre::Report::new(errors)).wrap_err_with(|| {
+                    format!(
+                        "The config recovered from {} is invalid, a backup was made at {}",
+                        path.display(),
+                        backup_path.display()
+                    )
+                });
+            }
+            return Ok(ReadState {
+                state,
+                migrated_from: None,
+                recovery: Some(Recovery::Custom { backup_path }),
+            });
+        }
+    };
+
+    match value {
+        Some(value) if lenient => {
+            let (state, recovery) = recover_lenient::<T>(value)?;
+            for pointer in &recovery.discarded {
+                match pointer.as_str() {
+                    "" => warn!("Discarded the whole config {}", path.display()),
+                    pointer => warn!(
+                        "Discarded invalid value at {pointer} in config {}",
+                        path.display()
+                    ),
+                }
+            }
+            Ok(ReadState {
+                state,
+                migrated_from: None,
+                recovery: Some(Recovery::Lenient {
+                    backup_path,
+                    recovery,
+                }),
+            })
+        }
+        _ => {
+            warn!("Reverting config {} to defaults", path.display());
+            Ok(ReadState {
+                state: T::default(),
+                migrated_from: None,
+                recovery: Some(Recovery::Default { backup_path }),
+            })
+        }
+    }
 }
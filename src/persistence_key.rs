@@ -1,22 +1,70 @@
+use crate::env_overrides::SEPARATOR;
+use crate::format::Format;
+use clap::ValueEnum;
+#[cfg(target_os = "linux")]
+use directories_next::BaseDirs;
 use directories_next::ProjectDirs;
+use eyre::OptionExt;
 use eyre::bail;
 use serde::Deserialize;
 use serde::Serialize;
+use std::fmt::Display;
 use std::path::PathBuf;
 
+/// Which of the project's directories a file is stored in.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DirectoryKind {
+    /// Settings, such as `~/.config/<project>` on Linux.
+    #[default]
+    Config,
+    /// Files the user would miss, such as `~/.local/share/<project>` on Linux.
+    Data,
+    /// Files which can be rebuilt when deleted, such as `~/.cache/<project>` on Linux.
+    Cache,
+    /// History and other state kept between runs, such as `~/.local/state/<project>` on Linux.
+    /// Other platforms have no such directory and use the local data directory.
+    State,
+    /// Sockets and other files only meaningful while running, such as `$XDG_RUNTIME_DIR/<project>`.
+    /// Only available on Linux.
+    Runtime,
+}
+
+impl Display for DirectoryKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DirectoryKind::Config => "config",
+            DirectoryKind::Data => "data",
+            DirectoryKind::Cache => "cache",
+            DirectoryKind::State => "state",
+            DirectoryKind::Runtime => "runtime",
+        })
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct PersistenceKey {
     pub project_name: PathBuf,
     pub file_slug: PathBuf,
+    /// Missing from keys saved by older versions, which were all configs.
+    #[serde(default)]
+    pub kind: DirectoryKind,
 }
 impl PersistenceKey {
     pub fn new(project_name: impl Into<PathBuf>, file_slug: impl Into<PathBuf>) -> Self {
         Self {
             project_name: project_name.into(),
             file_slug: file_slug.into(),
+            kind: DirectoryKind::Config,
         }
     }
 
+    /// Store the file in another of the project's directories, such as its cache.
+    pub fn with_kind(mut self, kind: DirectoryKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     pub fn file_path(&self) -> eyre::Result<PathBuf> {
         let dirs = ProjectDirs::from_path(self.project_name.clone());
         let Some(dirs) = dirs else {
@@ -27,8 +75,41 @@ impl PersistenceKey {
             );
         };
 
-        let config_path = dirs.config_dir().join(&self.file_slug);
-        Ok(config_path)
+        let dir = match self.kind {
+            DirectoryKind::Config => dirs.config_dir().to_path_buf(),
+            DirectoryKind::Data => dirs.data_dir().to_path_buf(),
+            DirectoryKind::Cache => dirs.cache_dir().to_path_buf(),
+            DirectoryKind::State => state_dir(&dirs, &self.project_name)?,
+            DirectoryKind::Runtime => dirs
+                .runtime_dir()
+                .ok_or_eyre(format!(
+                    "No runtime directory for project {}, is XDG_RUNTIME_DIR set?",
+                    self.project_name.display()
+                ))?
+                .to_path_buf(),
+        };
+        Ok(dir.join(&self.file_slug))
+    }
+
+    /// The format implied by the extension of the file slug.
+    pub fn format(&self) -> Format {
+        Format::from_path(&self.file_slug)
+    }
+
+    /// The prefix of environment variables overriding fields of this config,
+    /// such as `MY_PROJECT__SETTINGS` for project `my-project` and file slug `settings.json`.
+    /// Files of other kinds add it to the project, such as `MY_PROJECT_CACHE__SETTINGS`.
+    pub fn env_prefix(&self) -> String {
+        let stem = self
+            .file_slug
+            .file_stem()
+            .unwrap_or(self.file_slug.as_os_str());
+        let project = env_name(&self.project_name.to_string_lossy());
+        let project = match self.kind {
+            DirectoryKind::Config => project,
+            kind => format!("{project}_{}", env_name(&kind.to_string())),
+        };
+        format!("{project}{SEPARATOR}{}", env_name(&stem.to_string_lossy()))
     }
 
     pub async fn exists(&self) -> eyre::Result<bool> {
@@ -36,3 +117,32 @@ impl PersistenceKey {
         Ok(tokio::fs::try_exists(&path).await?)
     }
 }
+
+/// `$XDG_STATE_HOME/<project>` on Linux, falling back to `~/.local/state/<project>`.
+#[cfg(target_os = "linux")]
+fn state_dir(dirs: &ProjectDirs, project_name: &std::path::Path) -> eyre::Result<PathBuf> {
+    let _ = dirs;
+    if let Some(state_home) = std::env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+        && state_home.is_absolute()
+    {
+        return Ok(state_home.join(project_name));
+    }
+    let base = BaseDirs::new().ok_or_eyre("Failed to find the home directory")?;
+    Ok(base.home_dir().join(".local/state").join(project_name))
+}
+
+/// Platforms other than Linux have no state directory, so state is kept with local data.
+#[cfg(not(target_os = "linux"))]
+fn state_dir(dirs: &ProjectDirs, project_name: &std::path::Path) -> eyre::Result<PathBuf> {
+    let _ = project_name;
+    Ok(dirs.data_local_dir().to_path_buf())
+}
+
+fn env_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            c if c.is_ascii_alphanumeric() => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
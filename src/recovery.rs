@@ -0,0 +1,260 @@
+use crate::json_value;
+use crate::persistable_state::PersistableState;
+use cloud_terrastodon_user_input::Choice;
+use cloud_terrastodon_user_input::FzfArgs;
+use cloud_terrastodon_user_input::pick;
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::Value;
+use serde_path_to_error::Segment;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// The outcome of recovering a config that failed to deserialize.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LenientRecovery {
+    /// JSON pointers of the values that were discarded, in the order they were dropped.
+    /// An empty pointer means the whole config was discarded.
+    pub discarded: Vec<String>,
+}
+
+/// Deserialize as much of `value` as possible, discarding only the values which fail.
+///
+/// The value is merged onto the serialized default so missing fields are filled in,
+/// then each field that fails to deserialize or [validate](PersistableState::validate)
+/// is reset to its default, or removed if it has none, until the whole config is valid.
+pub fn recover_lenient<T: PersistableState>(value: Value) -> eyre::Result<(T, LenientRecovery)> {
+    let default = serde_json::to_value(T::default())?;
+    let mut merged = default.clone();
+    json_value::merge(&mut merged, value);
+    let mut discarded = Vec::<String>::new();
+    loop {
+        let invalid = match serde_path_to_error::deserialize::<_, T>(&merged) {
+            Ok(state) => match state.validate() {
+                Ok(()) => return Ok((state, LenientRecovery { discarded })),
+                Err(errors) => errors
+                    .errors
+                    .into_iter()
+                    .map(|error| error.path)
+                    .unique()
+                    .collect(),
+            },
+            Err(err) => vec![pointer_for(err.path())],
+        };
+        for mut pointer in invalid {
+            // When resetting a value did not help, the problem lies with its parent.
+            while discarded.contains(&pointer) {
+                match json_value::parent(&pointer) {
+                    Some(parent) => pointer = parent.to_string(),
+                    None => break,
+                }
+            }
+            if pointer.is_empty() {
+                discarded.push(pointer);
+                return Ok((T::default(), LenientRecovery { discarded }));
+            }
+            match default.pointer(&pointer) {
+                Some(default) => json_value::set(&mut merged, &pointer, default.clone()),
+                None => {
+                    json_value::remove(&mut merged, &pointer);
+                }
+            }
+            discarded.push(pointer);
+        }
+    }
+}
+
+fn pointer_for(path: &serde_path_to_error::Path) -> String {
+    let mut pointer = String::new();
+    for segment in path.iter() {
+        match segment {
+            Segment::Seq { index } => pointer.push_str(&format!("/{index}")),
+            Segment::Map { key } => pointer.push_str(&format!("/{}", json_value::escape(key))),
+            Segment::Enum { .. } => {}
+            Segment::Unknown => break,
+        }
+    }
+    pointer
+}
+
+/// How `load` handles a config file that exists but fails to deserialize or validate.
+#[derive(Debug, Clone)]
+pub enum RecoveryPolicy<T> {
+    /// Fail to load, leaving the file untouched.
+    Error,
+    /// Back up the file and revert to the default config.
+    BackupAndDefault,
+    /// Back up the file and keep every field that is still valid, see [`recover_lenient`].
+    Lenient,
+    /// Back up the file and ask the user how to proceed.
+    Prompt,
+    /// Back up the file and let the callback produce the config, which must pass validation.
+    Custom(fn(&RecoveryContext) -> eyre::Result<T>),
+}
+
+/// What a [`RecoveryPolicy::Custom`] callback gets to work with.
+#[derive(Debug)]
+pub struct RecoveryContext<'a> {
+    pub path: &'a Path,
+    pub backup_path: &'a Path,
+    /// The parsed contents upgraded to the current version, or `None` when the file could not be parsed at all.
+    pub value: Option<&'a Value>,
+    pub error: &'a eyre::Report,
+}
+
+/// The recovery that ran while loading a config.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Recovery {
+    /// The config was reverted to defaults.
+    Default { backup_path: PathBuf },
+    /// The invalid values were discarded.
+    Lenient {
+        backup_path: PathBuf,
+        #[serde(flatten)]
+        recovery: LenientRecovery,
+    },
+    /// The config was produced by a [`RecoveryPolicy::Custom`] callback.
+    Custom { backup_path: PathBuf },
+}
+
+/// A loaded config along with the recovery that ran, if the file was invalid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loaded<T> {
+    pub state: T,
+    pub recovery: Option<Recovery>,
+}
+
+/// The choices offered by [`RecoveryPolicy::Prompt`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PromptChoice {
+    Lenient,
+    Default,
+    Abort,
+}
+
+impl PromptChoice {
+    pub(crate) fn pick(path: &Path, error: &str) -> eyre::Result<Self> {
+        let choice = pick(FzfArgs {
+            choices: [
+                (PromptChoice::Lenient, "Keep the valid fields"),
+                (PromptChoice::Default, "Revert to defaults"),
+                (PromptChoice::Abort, "Abort loading"),
+            ]
+            .into_iter()
+            .map(|(value, key)| Choice {
+                key: key.to_string(),
+                value,
+            })
+            .collect(),
+            header: Some(format!(
+                "Config {} is invalid, a backup has been made: {error}",
+                path.display()
+            )),
+            ..Default::default()
+        })?;
+        Ok(choice.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence_key::PersistenceKey;
+    use crate::validation::ValidationErrors;
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Settings {
+        name: String,
+        server: Server,
+        aliases: BTreeMap<String, u16>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Server {
+        host: String,
+        port: u16,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Self {
+                name: "default".to_owned(),
+                server: Server {
+                    host: "localhost".to_owned(),
+                    port: 8080,
+                },
+                aliases: BTreeMap::new(),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl PersistableState for Settings {
+        async fn key() -> eyre::Result<PersistenceKey> {
+            Self::key_blocking()
+        }
+
+        fn key_blocking() -> eyre::Result<PersistenceKey> {
+            Ok(PersistenceKey::new("eye_config_tests", "recovery.json"))
+        }
+
+        fn validate(&self) -> Result<(), ValidationErrors> {
+            let mut errors = ValidationErrors::new();
+            if self.server.port == 0 {
+                errors.add("/server/port", "must not be 0");
+            }
+            errors.into_result()
+        }
+    }
+
+    #[test]
+    fn resets_a_nested_field_of_the_wrong_type() -> eyre::Result<()> {
+        let (state, recovery) = recover_lenient::<Settings>(json!({
+            "name": "mine",
+            "server": { "host": "example.com", "port": "not a number" },
+        }))?;
+
+        assert_eq!(recovery.discarded, ["/server/port"]);
+        assert_eq!(state.name, "mine");
+        assert_eq!(state.server.host, "example.com");
+        assert_eq!(state.server.port, 8080);
+        Ok(())
+    }
+
+    #[test]
+    fn resets_a_field_failing_validation() -> eyre::Result<()> {
+        let (state, recovery) = recover_lenient::<Settings>(json!({
+            "name": "mine",
+            "server": { "host": "example.com", "port": 0 },
+        }))?;
+
+        assert_eq!(recovery.discarded, ["/server/port"]);
+        assert_eq!(state.name, "mine");
+        assert_eq!(state.server.port, 8080);
+        Ok(())
+    }
+
+    #[test]
+    fn removes_an_invalid_value_without_a_default() -> eyre::Result<()> {
+        let (state, recovery) = recover_lenient::<Settings>(json!({
+            "aliases": { "web": 80, "broken": "eighty" },
+        }))?;
+
+        assert_eq!(recovery.discarded, ["/aliases/broken"]);
+        assert_eq!(state.aliases, BTreeMap::from([("web".to_owned(), 80)]));
+        Ok(())
+    }
+
+    #[test]
+    fn discards_everything_when_the_root_is_invalid() -> eyre::Result<()> {
+        let (state, recovery) = recover_lenient::<Settings>(json!("not a config"))?;
+
+        assert_eq!(recovery.discarded, [""]);
+        assert_eq!(state, Settings::default());
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! Keeping sensitive config values out of `Debug` output, logs and error messages.
+
+use crate::persistable_state::PersistableState;
+use crate::validation::ValidationErrors;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Printed in place of a redacted value.
+pub const REDACTED: &str = "[redacted]";
+
+/// Replaces the details of errors about secret configs.
+const HIDDEN: &str = "the details are hidden because the config is secret";
+
+/// Derive `Debug`, printing fields marked `#[secret]` as `[redacted]`.
+#[cfg(feature = "derive")]
+pub use eye_config_derive::RedactedDebug;
+
+/// A sensitive value which `Debug` prints as `[redacted]`, serialized exactly like the inner value.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> std::fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Errors from parsing or serializing can quote config values,
+/// so for secret configs the error is replaced with one that only says something went wrong.
+///
+/// [`ValidationErrors`] are kept, their messages come from the type's own `validate`.
+pub(crate) fn redact_error<T: PersistableState>(err: eyre::Report) -> eyre::Report {
+    match T::is_secret() && err.downcast_ref::<ValidationErrors>().is_none() {
+        true => eyre::eyre!(HIDDEN),
+        false => err,
+    }
+}
+
+/// The message to log for an error about a config, see [`redact_error`].
+pub(crate) fn describe_error<T: PersistableState>(err: &eyre::Report) -> String {
+    match T::is_secret() && err.downcast_ref::<ValidationErrors>().is_none() {
+        true => HIDDEN.to_string(),
+        false => err.to_string(),
+    }
+}
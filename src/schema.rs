@@ -0,0 +1,116 @@
+//! JSON Schemas for configs, so editors can validate and autocomplete hand edits.
+//!
+//! With the `schemars` feature, types deriving `JsonSchema` can expose their schema:
+//!
+//! ```ignore
+//! impl PersistableState for Settings {
+//!     // ...
+//!     fn schema() -> Option<schemars::Schema> {
+//!         Some(schemars::schema_for!(Self))
+//!     }
+//!
+//!     fn schema_reference() -> SchemaReference {
+//!         SchemaReference::Sidecar
+//!     }
+//! }
+//! ```
+
+#[cfg(feature = "schemars")]
+use crate::format::Format;
+#[cfg(feature = "schemars")]
+use crate::persistable_state::PersistableState;
+#[cfg(feature = "schemars")]
+use crate::persistence_key::PersistenceKey;
+#[cfg(feature = "schemars")]
+use crate::storage::StorageBackend;
+#[cfg(feature = "schemars")]
+use serde_json::Map;
+use serde_json::Value;
+#[cfg(feature = "schemars")]
+use tracing::warn;
+
+/// The field referencing the schema of a config file, removed when loading.
+pub const SCHEMA_FIELD: &str = "$schema";
+
+/// How saved config files point editors at their schema.
+#[cfg(feature = "schemars")]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SchemaReference {
+    /// Saved files do not reference the schema.
+    #[default]
+    None,
+    /// Write the schema to `<file stem>.schema.json` next to the config,
+    /// and reference it from the `$schema` field.
+    Sidecar,
+    /// Reference a schema published elsewhere, such as on the project's website, from the `$schema` field.
+    Url(String),
+}
+
+/// Remove the schema reference from a loaded value.
+pub fn take_schema_reference(value: &mut Value) {
+    if let Some(object) = value.as_object_mut() {
+        object.shift_remove(SCHEMA_FIELD);
+    }
+}
+
+/// The key of the sidecar schema of a config, such as `settings.schema.json` for `settings.toml`.
+#[cfg(feature = "schemars")]
+pub fn sidecar_key(key: &PersistenceKey) -> PersistenceKey {
+    PersistenceKey::new(
+        &key.project_name,
+        key.file_slug.with_extension("schema.json"),
+    )
+    .with_kind(key.kind)
+}
+
+/// Add the `$schema` field to a value before it is written, leaving RON untouched since it has no such convention.
+#[cfg(feature = "schemars")]
+pub(crate) fn with_schema_reference<T: PersistableState>(
+    key: &PersistenceKey,
+    value: Value,
+) -> Value {
+    let reference = match T::schema_reference() {
+        SchemaReference::None => return value,
+        SchemaReference::Sidecar => {
+            let sidecar = sidecar_key(key);
+            let file_name = sidecar.file_slug.file_name().unwrap_or_default();
+            // Editors resolve relative references against the config file.
+            format!("./{}", file_name.to_string_lossy())
+        }
+        SchemaReference::Url(url) => url,
+    };
+    match value {
+        Value::Object(object) if T::format(key) != Format::Ron => {
+            let mut referenced = Map::with_capacity(object.len() + 1);
+            referenced.insert(SCHEMA_FIELD.to_string(), Value::from(reference));
+            referenced.extend(object);
+            Value::Object(referenced)
+        }
+        other => other,
+    }
+}
+
+/// Write the sidecar schema of a config if it uses one and the stored schema is outdated.
+/// The caller is responsible for holding the exclusive lock.
+#[cfg(feature = "schemars")]
+pub(crate) fn write_sidecar<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+) -> eyre::Result<()> {
+    if T::schema_reference() != SchemaReference::Sidecar {
+        return Ok(());
+    }
+    let Some(schema) = T::schema() else {
+        warn!(
+            "Config {} references a sidecar schema but `schema` returns None",
+            storage.location(key)?.display()
+        );
+        return Ok(());
+    };
+    let content = serde_json::to_string_pretty(&schema)?;
+    let sidecar = sidecar_key(key);
+    if storage.read(&sidecar)?.as_deref() == Some(content.as_bytes()) {
+        return Ok(());
+    }
+    storage.write(&sidecar, content.as_bytes())
+}
@@ -0,0 +1,253 @@
+use crate::atomic_write::write_atomic_with_mode;
+use crate::backups::Backup;
+use crate::backups::BackupRetention;
+use crate::backups::list_backups;
+use crate::backups::write_backup;
+use crate::file_lock::FileLock;
+use crate::file_lock::LockMode;
+use crate::persistence_key::DirectoryKind;
+use crate::persistence_key::PersistenceKey;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::LazyLock;
+use std::sync::RwLock;
+
+/// Where configs are stored, every read and write made by `PersistableState` goes through a backend.
+///
+/// Methods block, the async API runs them on the blocking thread pool.
+pub trait StorageBackend: std::fmt::Debug + Send + Sync {
+    /// A path identifying the stored config, used in messages and to track external changes.
+    /// Backends which do not store files may return any path unique to the key.
+    fn location(&self, key: &PersistenceKey) -> eyre::Result<PathBuf>;
+
+    /// The stored contents, or `None` if nothing is stored for the key.
+    fn read(&self, key: &PersistenceKey) -> eyre::Result<Option<Vec<u8>>>;
+
+    /// Replace the stored contents, readers must only ever observe the old or the new contents.
+    fn write(&self, key: &PersistenceKey, contents: &[u8]) -> eyre::Result<()>;
+
+    /// Like [`write`](StorageBackend::write), restricting access to the stored config to the Unix permission bits `mode`.
+    /// By default, the mode is ignored.
+    fn write_with_mode(
+        &self,
+        key: &PersistenceKey,
+        contents: &[u8],
+        mode: Option<u32>,
+    ) -> eyre::Result<()> {
+        let _ = mode;
+        self.write(key, contents)
+    }
+
+    /// The Unix permission bits of the stored config, or `None` if nothing is stored or the backend has no permissions.
+    fn mode(&self, key: &PersistenceKey) -> eyre::Result<Option<u32>> {
+        let _ = key;
+        Ok(None)
+    }
+
+    fn exists(&self, key: &PersistenceKey) -> eyre::Result<bool> {
+        Ok(self.read(key)?.is_some())
+    }
+
+    /// Remove the stored contents, doing nothing if nothing is stored.
+    fn remove(&self, key: &PersistenceKey) -> eyre::Result<()>;
+
+    /// The keys stored for a project in one kind of directory.
+    fn list(&self, project_name: &Path, kind: DirectoryKind) -> eyre::Result<Vec<PersistenceKey>>;
+
+    /// Keep a copy of the current contents, returning the location of the copy.
+    fn backup(&self, key: &PersistenceKey, retention: BackupRetention) -> eyre::Result<PathBuf>;
+
+    /// The backups made by [`backup`](StorageBackend::backup) which are still kept, newest first.
+    /// By default, no backups are listed.
+    fn list_backups(&self, key: &PersistenceKey) -> eyre::Result<Vec<Backup>> {
+        let _ = key;
+        Ok(Vec::new())
+    }
+
+    /// Block until the config can be accessed in the given mode, coordinating with other processes.
+    /// By default, no locking is done.
+    fn lock(&self, key: &PersistenceKey, mode: LockMode) -> eyre::Result<StorageLock> {
+        let _ = (key, mode);
+        Ok(StorageLock::default())
+    }
+}
+
+/// Returned by [`StorageBackend::lock`], the lock is released when this is dropped.
+#[derive(Default)]
+pub struct StorageLock {
+    _guard: Option<Box<dyn Send + Sync>>,
+}
+
+impl StorageLock {
+    /// Hold `guard` until the lock is dropped.
+    pub fn new(guard: impl Send + Sync + 'static) -> Self {
+        Self {
+            _guard: Some(Box::new(guard)),
+        }
+    }
+}
+
+/// Stores each config as a file, by default in the user's config directory, see [`PersistenceKey::file_path`].
+#[derive(Debug, Clone, Default)]
+pub struct FilesystemBackend {
+    root: Option<PathBuf>,
+}
+
+impl FilesystemBackend {
+    /// Store configs as `<root>/<project_name>/<file_slug>` instead of in the user's config directory,
+    /// and files of other [`DirectoryKind`]s as `<root>/<kind>/<project_name>/<file_slug>`.
+    pub fn in_dir(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: Some(root.into()),
+        }
+    }
+
+    /// The file a config is stored in.
+    pub fn path(&self, key: &PersistenceKey) -> eyre::Result<PathBuf> {
+        match (&self.root, key.kind) {
+            (Some(root), DirectoryKind::Config) => {
+                Ok(root.join(&key.project_name).join(&key.file_slug))
+            }
+            (Some(root), kind) => Ok(root
+                .join(kind.to_string())
+                .join(&key.project_name)
+                .join(&key.file_slug)),
+            (None, _) => key.file_path(),
+        }
+    }
+}
+
+impl StorageBackend for FilesystemBackend {
+    fn location(&self, key: &PersistenceKey) -> eyre::Result<PathBuf> {
+        self.path(key)
+    }
+
+    fn read(&self, key: &PersistenceKey) -> eyre::Result<Option<Vec<u8>>> {
+        let path = self.path(key)?;
+        if !fs::exists(&path)? {
+            return Ok(None);
+        }
+        Ok(Some(fs::read(&path)?))
+    }
+
+    fn write(&self, key: &PersistenceKey, contents: &[u8]) -> eyre::Result<()> {
+        self.write_with_mode(key, contents, None)
+    }
+
+    fn write_with_mode(
+        &self,
+        key: &PersistenceKey,
+        contents: &[u8],
+        mode: Option<u32>,
+    ) -> eyre::Result<()> {
+        let path = self.path(key)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        write_atomic_with_mode(&path, contents, mode)
+    }
+
+    #[cfg(unix)]
+    fn mode(&self, key: &PersistenceKey) -> eyre::Result<Option<u32>> {
+        use std::os::unix::fs::PermissionsExt;
+        let path = self.path(key)?;
+        if !fs::exists(&path)? {
+            return Ok(None);
+        }
+        Ok(Some(fs::metadata(&path)?.permissions().mode() & 0o777))
+    }
+
+    fn exists(&self, key: &PersistenceKey) -> eyre::Result<bool> {
+        Ok(fs::exists(self.path(key)?)?)
+    }
+
+    fn remove(&self, key: &PersistenceKey) -> eyre::Result<()> {
+        let path = self.path(key)?;
+        if fs::exists(&path)? {
+            fs::remove_file(&path)?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, project_name: &Path, kind: DirectoryKind) -> eyre::Result<Vec<PersistenceKey>> {
+        let dir = self.path(&PersistenceKey::new(project_name, "").with_kind(kind))?;
+        if !fs::exists(&dir)? {
+            return Ok(Vec::new());
+        }
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let file_name = entry.file_name();
+            let name = file_name.to_string_lossy();
+            // Skip lock files, temp files, backups and sidecar schemas.
+            if name.starts_with('.') || name.ends_with(".bak") || name.ends_with(".schema.json") {
+                continue;
+            }
+            keys.push(PersistenceKey::new(project_name, file_name).with_kind(kind));
+        }
+        keys.sort_by(|a, b| a.file_slug.cmp(&b.file_slug));
+        Ok(keys)
+    }
+
+    fn backup(&self, key: &PersistenceKey, retention: BackupRetention) -> eyre::Result<PathBuf> {
+        write_backup(&self.path(key)?, retention)
+    }
+
+    fn list_backups(&self, key: &PersistenceKey) -> eyre::Result<Vec<Backup>> {
+        list_backups(&self.path(key)?)
+    }
+
+    fn lock(&self, key: &PersistenceKey, mode: LockMode) -> eyre::Result<StorageLock> {
+        let lock = FileLock::acquire(&self.path(key)?, mode)?;
+        Ok(lock.map(StorageLock::new).unwrap_or_default())
+    }
+}
+
+static STORAGE: LazyLock<RwLock<Arc<dyn StorageBackend>>> =
+    LazyLock::new(|| RwLock::new(Arc::new(FilesystemBackend::default())));
+
+#[cfg(feature = "testing")]
+thread_local! {
+    static THREAD_STORAGE: std::cell::RefCell<Option<Arc<dyn StorageBackend>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Replace the backend used on the current thread, returning the previous one.
+#[cfg(feature = "testing")]
+pub(crate) fn replace_thread_storage_backend(
+    backend: Option<Arc<dyn StorageBackend>>,
+) -> Option<Arc<dyn StorageBackend>> {
+    THREAD_STORAGE.with(|storage| storage.replace(backend))
+}
+
+/// The backend used by types that do not override `PersistableState::storage`.
+pub fn storage_backend() -> Arc<dyn StorageBackend> {
+    #[cfg(feature = "testing")]
+    if let Some(backend) = THREAD_STORAGE.with(|storage| storage.borrow().clone()) {
+        return backend;
+    }
+    STORAGE
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// Change the backend used by types that do not override `PersistableState::storage`.
+/// Configs are stored as files unless this is called.
+pub fn set_storage_backend(backend: Arc<dyn StorageBackend>) {
+    *STORAGE
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = backend;
+}
+
+/// Run blocking storage work on the blocking thread pool.
+pub(crate) async fn blocking<R: Send + 'static>(
+    work: impl FnOnce() -> eyre::Result<R> + Send + 'static,
+) -> eyre::Result<R> {
+    tokio::task::spawn_blocking(work).await?
+}
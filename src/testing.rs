@@ -0,0 +1,297 @@
+//! Helpers for testing code which uses `PersistableState` without touching the user's real configs.
+//!
+//! ```no_run
+//! # use eye_config::persistable_state::PersistableState;
+//! # use eye_config::persistence_key::PersistenceKey;
+//! # use eye_config::testing::Sandbox;
+//! # #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Default)]
+//! # struct PreferredModelConfig {
+//! #     preferred_model: Option<String>,
+//! # }
+//! # #[eye_config::async_trait::async_trait]
+//! # impl PersistableState for PreferredModelConfig {
+//! #     async fn key() -> eyre::Result<PersistenceKey> {
+//! #         Ok(PersistenceKey::new("my_project", "preferred_model.json"))
+//! #     }
+//! # }
+//! #[tokio::test]
+//! async fn saves_the_model() -> eyre::Result<()> {
+//!     let sandbox = Sandbox::new()?;
+//!     PreferredModelConfig::update(|config| config.preferred_model = Some("gpt".into())).await?;
+//!     sandbox.assert_saved::<PreferredModelConfig>().await;
+//!     sandbox.assert_registry_contains(&PreferredModelConfig::key().await?).await;
+//!     Ok(())
+//! }
+//! # fn main() {}
+//! ```
+
+use crate::backups::Backup;
+use crate::backups::BackupRetention;
+use crate::cli::config::known_projects::KnownProjects;
+use crate::persistable_state::PersistableState;
+use crate::persistable_state::parse_state;
+use crate::persistence_key::DirectoryKind;
+use crate::persistence_key::PersistenceKey;
+use crate::storage::FilesystemBackend;
+use crate::storage::StorageBackend;
+use crate::storage::replace_thread_storage_backend;
+use chrono::DateTime;
+use chrono::Utc;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use tempfile::TempDir;
+
+static MEMORY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps configs in memory, so nothing is written to disk.
+///
+/// Backups are kept in memory too and retention is ignored. Watching is not supported.
+#[derive(Debug)]
+pub struct MemoryBackend {
+    id: u64,
+    files: Mutex<HashMap<PersistenceKey, Vec<u8>>>,
+    backups: Mutex<HashMap<PersistenceKey, Vec<MemoryBackup>>>,
+}
+
+#[derive(Debug)]
+struct MemoryBackup {
+    timestamp: DateTime<Utc>,
+    contents: Vec<u8>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self {
+            id: MEMORY_COUNTER.fetch_add(1, Ordering::Relaxed),
+            files: Default::default(),
+            backups: Default::default(),
+        }
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The contents of every backup made of a config, oldest first.
+    pub fn backups(&self, key: &PersistenceKey) -> Vec<Vec<u8>> {
+        self.backups
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(key)
+            .map(|backups| {
+                backups
+                    .iter()
+                    .map(|backup| backup.contents.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The location reported for the `number`th backup of a config, counting from 1.
+    fn backup_location(&self, key: &PersistenceKey, number: usize) -> eyre::Result<PathBuf> {
+        let location = self.location(key)?;
+        let file_name = location.file_name().unwrap_or_default().to_string_lossy();
+        Ok(location.with_file_name(format!("{file_name}.{number}.bak")))
+    }
+
+    fn files(&self) -> std::sync::MutexGuard<'_, HashMap<PersistenceKey, Vec<u8>>> {
+        self.files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl StorageBackend for MemoryBackend {
+    fn location(&self, key: &PersistenceKey) -> eyre::Result<PathBuf> {
+        // Unique per backend, so separate backends never share conflict tracking.
+        let root = PathBuf::from(format!("memory://{}", self.id));
+        let root = match key.kind {
+            DirectoryKind::Config => root,
+            kind => root.join(kind.to_string()),
+        };
+        Ok(root.join(&key.project_name).join(&key.file_slug))
+    }
+
+    fn read(&self, key: &PersistenceKey) -> eyre::Result<Option<Vec<u8>>> {
+        Ok(self.files().get(key).cloned())
+    }
+
+    fn write(&self, key: &PersistenceKey, contents: &[u8]) -> eyre::Result<()> {
+        self.files().insert(key.clone(), contents.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, key: &PersistenceKey) -> eyre::Result<()> {
+        self.files().remove(key);
+        Ok(())
+    }
+
+    fn list(&self, project_name: &Path, kind: DirectoryKind) -> eyre::Result<Vec<PersistenceKey>> {
+        let mut keys = self
+            .files()
+            .keys()
+            .filter(|key| key.project_name == project_name && key.kind == kind)
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort_by(|a, b| a.file_slug.cmp(&b.file_slug));
+        Ok(keys)
+    }
+
+    fn backup(&self, key: &PersistenceKey, retention: BackupRetention) -> eyre::Result<PathBuf> {
+        let _ = retention;
+        let contents = self.read(key)?.unwrap_or_default();
+        let mut backups = self
+            .backups
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let backups = backups.entry(key.clone()).or_default();
+        backups.push(MemoryBackup {
+            timestamp: Utc::now(),
+            contents,
+        });
+        self.backup_location(key, backups.len())
+    }
+
+    fn list_backups(&self, key: &PersistenceKey) -> eyre::Result<Vec<Backup>> {
+        let backups = self
+            .backups
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let Some(backups) = backups.get(key) else {
+            return Ok(Vec::new());
+        };
+        let mut listed = Vec::with_capacity(backups.len());
+        for (index, backup) in backups.iter().enumerate().rev() {
+            listed.push(Backup {
+                path: self.backup_location(key, index + 1)?,
+                timestamp: backup.timestamp,
+                sequence: index as u32,
+                len: backup.contents.len() as u64,
+            });
+        }
+        Ok(listed)
+    }
+}
+
+/// Redirects every config on the current thread to a fresh temp directory or another backend, until dropped.
+///
+/// This applies to types that do not override `PersistableState::storage`, including the
+/// registry of known projects. Sandboxes are per thread so parallel tests do not interfere,
+/// which means async tests should use the default current thread `#[tokio::test]` runtime.
+#[derive(Debug)]
+pub struct Sandbox {
+    backend: Arc<dyn StorageBackend>,
+    previous: Option<Arc<dyn StorageBackend>>,
+    dir: Option<TempDir>,
+    /// The guard restores the backend of the thread it was created on.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Sandbox {
+    /// Store configs as files in a temp directory, removed when the sandbox is dropped.
+    pub fn new() -> eyre::Result<Self> {
+        let dir = tempfile::Builder::new().prefix("eye_config-").tempdir()?;
+        let backend = Arc::new(FilesystemBackend::in_dir(dir.path()));
+        Ok(Self::install(backend, Some(dir)))
+    }
+
+    /// Store configs in a fresh [`MemoryBackend`].
+    pub fn in_memory() -> Self {
+        Self::install(Arc::new(MemoryBackend::new()), None)
+    }
+
+    /// Store configs in the given backend.
+    pub fn with_backend(backend: Arc<dyn StorageBackend>) -> Self {
+        Self::install(backend, None)
+    }
+
+    fn install(backend: Arc<dyn StorageBackend>, dir: Option<TempDir>) -> Self {
+        let previous = replace_thread_storage_backend(Some(backend.clone()));
+        Self {
+            backend,
+            previous,
+            dir,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// The temp directory holding the configs, if the sandbox stores files.
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_ref().map(|dir| dir.path())
+    }
+
+    pub fn backend(&self) -> &Arc<dyn StorageBackend> {
+        &self.backend
+    }
+
+    /// Whether anything was stored for the type's key.
+    pub async fn was_saved<T: PersistableState>(&self) -> eyre::Result<bool> {
+        self.backend.exists(&T::key().await?)
+    }
+
+    /// Panic unless something was stored for the type's key.
+    pub async fn assert_saved<T: PersistableState>(&self) {
+        let key = T::key().await.expect("failed to get the key");
+        let saved = self
+            .backend
+            .exists(&key)
+            .expect("failed to check the storage");
+        assert!(saved, "expected a config to be saved for {key:?}");
+    }
+
+    /// Panic unless the stored config equals `expected` once loaded.
+    pub async fn assert_contents<T: PersistableState>(&self, expected: &T) {
+        let key = T::key().await.expect("failed to get the key");
+        let content = self
+            .backend
+            .read(&key)
+            .expect("failed to read the storage")
+            .unwrap_or_else(|| panic!("expected a config to be saved for {key:?}"));
+        let actual = parse_state::<T>(&key, &content)
+            .expect("failed to parse the stored config")
+            .state;
+        if T::is_secret() {
+            assert!(
+                &actual == expected,
+                "stored config for {key:?} differs, the values are hidden because the config is secret"
+            );
+        } else {
+            assert_eq!(&actual, expected, "stored config for {key:?} differs");
+        }
+    }
+
+    /// Panic unless the registry of known projects contains the key.
+    pub async fn assert_registry_contains(&self, key: &PersistenceKey) {
+        let registry_key = KnownProjects::key().await.expect("failed to get the key");
+        let known_projects = match self
+            .backend
+            .read(&registry_key)
+            .expect("failed to read the storage")
+        {
+            Some(content) => {
+                parse_state::<KnownProjects>(&registry_key, &content)
+                    .expect("failed to parse the registry")
+                    .state
+            }
+            None => KnownProjects::default(),
+        };
+        assert!(
+            known_projects.entries.iter().any(|entry| &entry.key == key),
+            "expected the registry to contain {key:?}"
+        );
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        replace_thread_storage_backend(self.previous.take());
+    }
+}
@@ -0,0 +1,72 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A problem with one value of a config, found by [`PersistableState::validate`](crate::persistable_state::PersistableState::validate).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationError {
+    /// The JSON pointer of the value, such as `/server/port`. The empty pointer is the whole config.
+    pub path: String,
+    pub message: String,
+}
+
+/// Every problem found when validating a config, displayed as one line per problem.
+///
+/// ```ignore
+/// fn validate(&self) -> Result<(), ValidationErrors> {
+///     let mut errors = ValidationErrors::new();
+///     if self.port == 0 {
+///         errors.add("/port", "must not be 0");
+///     }
+///     if self.tls && self.insecure {
+///         errors.add("", "tls and insecure are mutually exclusive");
+///     }
+///     errors.into_result()
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<ValidationError>,
+}
+
+impl ValidationErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a problem with the value at a JSON pointer.
+    pub fn add(&mut self, path: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(ValidationError {
+            path: path.into(),
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// `Ok` if no problems were recorded.
+    pub fn into_result(self) -> Result<(), Self> {
+        match self.is_empty() {
+            true => Ok(()),
+            false => Err(self),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, error) in self.errors.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            match error.path.as_str() {
+                "" => write!(f, "{}", error.message)?,
+                path => write!(f, "{path}: {}", error.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
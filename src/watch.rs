@@ -0,0 +1,178 @@
+use crate::changes::ChangeOrigin;
+use crate::changes::publish;
+use crate::conflict::Fingerprint;
+use crate::conflict::Observed;
+use crate::conflict::last_observed;
+use crate::conflict::record_observed;
+use crate::env_overrides::apply_env_overrides;
+use crate::file_lock::LockMode;
+use crate::persistable_state::PersistableState;
+use crate::persistable_state::parse_state;
+use crate::persistence_key::PersistenceKey;
+use crate::redact::redact_error;
+use crate::storage::StorageBackend;
+use crate::storage::blocking;
+use eyre::OptionExt;
+use notify::RecommendedWatcher;
+use notify::RecursiveMode;
+use notify::Watcher;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+/// How long the file must be quiet before a burst of events is treated as a single change.
+pub const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Receives a freshly loaded config whenever its file is changed by another process.
+///
+/// Files that fail to parse or validate are reported as errors rather than replaced with defaults,
+/// and the watcher keeps running so a later fix is picked up.
+pub struct StateWatcher<T> {
+    receiver: mpsc::UnboundedReceiver<eyre::Result<T>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl<T: PersistableState> StateWatcher<T> {
+    pub async fn new(key: PersistenceKey) -> eyre::Result<Self> {
+        let storage = T::storage();
+        let path = storage.location(&key)?;
+        let dir = path
+            .parent()
+            .ok_or_eyre("Cannot watch a config without a parent directory")?
+            .to_path_buf();
+        tokio::fs::create_dir_all(&dir).await?;
+
+        // The directory is watched rather than the file, since atomic saves replace the file.
+        let (event_sender, mut events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = event_sender.send(event);
+        })?;
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut last_seen = blocking({
+            let storage = storage.clone();
+            let key = key.clone();
+            move || Fingerprint::read(&*storage, &key)
+        })
+        .await?;
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(err) => {
+                        if sender.send(Err(err.into())).is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+                if !event
+                    .paths
+                    .iter()
+                    .any(|changed| is_same_file(changed, &path))
+                {
+                    continue;
+                }
+
+                // Wait for the burst of events from a single save to settle.
+                loop {
+                    match tokio::time::timeout(DEBOUNCE, events.recv()).await {
+                        Ok(Some(_)) => continue,
+                        Ok(None) => return,
+                        Err(_) => break,
+                    }
+                }
+
+                let read = blocking({
+                    let storage = storage.clone();
+                    let key = key.clone();
+                    let mut seen = last_seen.clone();
+                    move || {
+                        let read = read_changed::<T>(&*storage, &key, &mut seen);
+                        Ok((seen, read))
+                    }
+                })
+                .await;
+                let read = match read {
+                    Ok((seen, read)) => {
+                        last_seen = seen;
+                        read
+                    }
+                    Err(err) => Err(err),
+                };
+                let Some(message) = read.transpose() else {
+                    continue;
+                };
+                if sender.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            receiver,
+            _watcher: watcher,
+        })
+    }
+
+    /// Wait for the next change, returning `None` once the watcher has stopped.
+    pub async fn recv(&mut self) -> Option<eyre::Result<T>> {
+        self.receiver.recv().await
+    }
+}
+
+fn is_same_file(changed: &Path, path: &Path) -> bool {
+    changed.file_name() == path.file_name()
+}
+
+/// Read the config if its contents differ from the last version seen by the watcher
+/// and from the last version written by this process.
+/// A delivered change is recorded as observed, so saving it afterwards is not a conflict.
+fn read_changed<T: PersistableState>(
+    storage: &dyn StorageBackend,
+    key: &PersistenceKey,
+    last_seen: &mut Option<Fingerprint>,
+) -> eyre::Result<Option<T>> {
+    let path = storage.location(key)?;
+    let _lock = storage.lock(key, LockMode::Shared)?;
+    let content = storage.read(key)?;
+    let fingerprint = content.as_deref().map(Fingerprint::new);
+    if fingerprint == *last_seen {
+        return Ok(None);
+    }
+    *last_seen = fingerprint.clone();
+    if last_observed(&path).is_some_and(|observed| observed.fingerprint == fingerprint) {
+        debug!("Ignoring change to {} made by this process", path.display());
+        return Ok(None);
+    }
+    let Some(content) = content else {
+        debug!("Config {} was removed, using defaults", path.display());
+        let state = T::default();
+        record_observed(
+            &path,
+            Observed {
+                fingerprint: None,
+                snapshot: serde_json::to_value(&state)?,
+            },
+        );
+        let state = apply_env_overrides(key, state)?;
+        publish(&path, &state, ChangeOrigin::ExternalChange);
+        return Ok(Some(state));
+    };
+    let state = parse_state::<T>(key, &content)
+        .map_err(redact_error::<T>)?
+        .state;
+    state.validate()?;
+    record_observed(
+        &path,
+        Observed {
+            fingerprint,
+            snapshot: serde_json::to_value(&state)?,
+        },
+    );
+    let state = apply_env_overrides(key, state)?;
+    publish(&path, &state, ChangeOrigin::ExternalChange);
+    Ok(Some(state))
+}
@@ -0,0 +1,114 @@
+use eye_config::layered::Layer;
+use eye_config::persistable_state::PersistableState;
+use eye_config::persistence_key::PersistenceKey;
+use eye_config::testing::Sandbox;
+use serde::Deserialize;
+use serde::Serialize;
+
+const ENV_PREFIX: &str = "EYE_CONFIG_LAYERED_TESTS";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+struct Settings {
+    name: String,
+    system: u64,
+    user: u64,
+    env: u64,
+    overridden: u64,
+}
+
+#[async_trait::async_trait]
+impl PersistableState for Settings {
+    async fn key() -> eyre::Result<PersistenceKey> {
+        Self::key_blocking()
+    }
+
+    fn key_blocking() -> eyre::Result<PersistenceKey> {
+        Ok(PersistenceKey::new("eye_config_tests", "layered.json"))
+    }
+}
+
+/// A system dir holding a file which sets `system`.
+fn system_dir() -> eyre::Result<tempfile::TempDir> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("layered.json"), r#"{"system": 1}"#)?;
+    Ok(dir)
+}
+
+#[tokio::test]
+async fn reports_the_layer_of_each_field() -> eyre::Result<()> {
+    let sandbox = Sandbox::in_memory();
+    sandbox
+        .backend()
+        .write(&Settings::key().await?, br#"{"user": 2}"#)?;
+    let system = system_dir()?;
+    // SAFETY: no other test in this binary reads this variable.
+    unsafe { std::env::set_var(format!("{ENV_PREFIX}__ENV"), "3") };
+
+    let layered = Settings::layered()
+        .await?
+        .system_dir(Some(system.path().to_path_buf()))
+        .project_dir(None)
+        .env_prefix(Some(ENV_PREFIX.to_owned()))
+        .set("/overridden", 4)
+        .load()
+        .await?;
+
+    assert_eq!(layered.source_of("/name"), Some(Layer::Default));
+    assert_eq!(layered.source_of("/system"), Some(Layer::System));
+    assert_eq!(layered.source_of("/user"), Some(Layer::User));
+    assert_eq!(layered.source_of("/env"), Some(Layer::Env));
+    assert_eq!(layered.source_of("/overridden"), Some(Layer::Override));
+    assert_eq!(layered.source_of(""), Some(Layer::Override));
+    assert_eq!(
+        [
+            layered.value.system,
+            layered.value.user,
+            layered.value.env,
+            layered.value.overridden
+        ],
+        [1, 2, 3, 4]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn keeps_lower_layers_without_a_user_file() -> eyre::Result<()> {
+    let _sandbox = Sandbox::in_memory();
+    let system = system_dir()?;
+
+    let layered = Settings::layered()
+        .await?
+        .system_dir(Some(system.path().to_path_buf()))
+        .project_dir(None)
+        .env_prefix(None)
+        .load()
+        .await?;
+
+    assert_eq!(layered.source_of("/name"), Some(Layer::Default));
+    assert_eq!(layered.source_of("/system"), Some(Layer::System));
+    assert_eq!(layered.source_of("/user"), Some(Layer::Default));
+    assert_eq!(layered.sources().len(), 5);
+    Ok(())
+}
+
+#[tokio::test]
+async fn an_empty_user_file_overrides_nothing() -> eyre::Result<()> {
+    let sandbox = Sandbox::in_memory();
+    sandbox.backend().write(&Settings::key().await?, b"{}")?;
+
+    let layered = Settings::layered()
+        .await?
+        .system_dir(None)
+        .project_dir(None)
+        .env_prefix(None)
+        .load()
+        .await?;
+
+    assert!(
+        layered
+            .sources()
+            .values()
+            .all(|layer| *layer == Layer::Default)
+    );
+    Ok(())
+}
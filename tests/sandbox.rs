@@ -0,0 +1,290 @@
+use eye_config::backups::BackupRetention;
+use eye_config::blocking::BlockingPersistableState;
+use eye_config::config_handle::ConfigHandle;
+use eye_config::conflict::ConflictPolicy;
+use eye_config::persistable_state::PersistableState;
+use eye_config::persistence_key::DirectoryKind;
+use eye_config::persistence_key::PersistenceKey;
+use eye_config::recovery::RecoveryPolicy;
+use eye_config::storage::FilesystemBackend;
+use eye_config::storage::StorageBackend;
+use eye_config::testing::MemoryBackend;
+use eye_config::testing::Sandbox;
+use eye_config::validation::ValidationErrors;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Barrier;
+use std::time::Duration;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+struct Counter {
+    count: u64,
+}
+
+#[async_trait::async_trait]
+impl PersistableState for Counter {
+    async fn key() -> eyre::Result<PersistenceKey> {
+        Self::key_blocking()
+    }
+
+    fn key_blocking() -> eyre::Result<PersistenceKey> {
+        Ok(PersistenceKey::new("eye_config_tests", "counter.json"))
+    }
+}
+
+#[test]
+fn sandboxes_on_different_threads_do_not_interfere() {
+    let barrier = Arc::new(Barrier::new(2));
+    let threads = [1, 2].map(|count| {
+        let barrier = barrier.clone();
+        std::thread::spawn(move || -> eyre::Result<u64> {
+            let _sandbox = Sandbox::in_memory();
+            Counter::update_blocking(|counter| counter.count = count)?;
+            // Both threads have saved before either loads.
+            barrier.wait();
+            Ok(Counter::load_blocking()?.count)
+        })
+    });
+    let counts = threads.map(|thread| thread.join().unwrap().unwrap());
+    assert_eq!(counts, [1, 2]);
+}
+
+#[test]
+fn dropping_a_sandbox_restores_the_previous_one() -> eyre::Result<()> {
+    let outer = Sandbox::in_memory();
+    Counter::update_blocking(|counter| counter.count = 1)?;
+    {
+        let _inner = Sandbox::in_memory();
+        assert_eq!(Counter::load_blocking()?.count, 0);
+        Counter::update_blocking(|counter| counter.count = 2)?;
+    }
+    assert_eq!(Counter::load_blocking()?.count, 1);
+    assert!(outer.backend().exists(&Counter::key_blocking()?)?);
+    Ok(())
+}
+
+#[tokio::test]
+async fn saves_into_the_sandbox_dir() -> eyre::Result<()> {
+    let sandbox = Sandbox::new()?;
+    Counter::update(|counter| counter.count = 3).await?;
+
+    sandbox.assert_saved::<Counter>().await;
+    sandbox.assert_contents(&Counter { count: 3 }).await;
+    let dir = sandbox.dir().expect("the sandbox stores files");
+    assert!(dir.join("eye_config_tests").join("counter.json").exists());
+    Ok(())
+}
+
+#[tokio::test]
+async fn redirects_the_registry_of_known_projects() -> eyre::Result<()> {
+    let sandbox = Sandbox::new()?;
+    Counter::update(|counter| counter.count = 4).await?;
+
+    sandbox
+        .assert_registry_contains(&Counter::key().await?)
+        .await;
+    let dir = sandbox.dir().expect("the sandbox stores files");
+    assert!(dir.join("eye_config").join("known-projects.json").exists());
+    Ok(())
+}
+
+#[tokio::test]
+#[should_panic(expected = "stored config")]
+async fn assert_contents_panics_on_a_difference() {
+    let sandbox = Sandbox::in_memory();
+    Counter::update(|counter| counter.count = 5).await.unwrap();
+    sandbox.assert_contents(&Counter { count: 6 }).await;
+}
+
+#[test]
+fn lists_each_kind_of_directory_separately() -> eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let backends: [Arc<dyn StorageBackend>; 2] = [
+        Arc::new(FilesystemBackend::in_dir(dir.path())),
+        Arc::new(MemoryBackend::new()),
+    ];
+    for backend in backends {
+        let project = Path::new("eye_config_tests");
+        let config = PersistenceKey::new(project, "settings.json");
+        let cache = PersistenceKey::new(project, "settings.json").with_kind(DirectoryKind::Cache);
+        backend.write(&config, b"{}")?;
+        backend.write(&cache, b"{}")?;
+
+        assert_eq!(backend.list(project, DirectoryKind::Config)?, [config]);
+        assert_eq!(backend.list(project, DirectoryKind::Cache)?, [cache]);
+        assert!(backend.list(project, DirectoryKind::Data)?.is_empty());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+struct Reapplied {
+    count: u64,
+    note: String,
+}
+
+#[async_trait::async_trait]
+impl PersistableState for Reapplied {
+    async fn key() -> eyre::Result<PersistenceKey> {
+        Self::key_blocking()
+    }
+
+    fn key_blocking() -> eyre::Result<PersistenceKey> {
+        Ok(PersistenceKey::new("eye_config_tests", "reapplied.json"))
+    }
+
+    fn conflict_policy() -> ConflictPolicy {
+        ConflictPolicy::ReloadAndReapply
+    }
+}
+
+#[tokio::test]
+async fn keeps_external_changes_across_saves_after_reapplying() -> eyre::Result<()> {
+    let sandbox = Sandbox::in_memory();
+    let mut config = Reapplied::load().await?;
+    config.modify_and_save(|config| config.count = 1).await?;
+    let external = Reapplied {
+        count: 1,
+        note: "external".to_owned(),
+    };
+    sandbox
+        .backend()
+        .write(&Reapplied::key().await?, &serde_json::to_vec(&external)?)?;
+
+    config.modify_and_save(|config| config.count = 2).await?;
+    assert_eq!(config.note, "external");
+    config.modify_and_save(|config| config.count = 3).await?;
+    sandbox
+        .assert_contents(&Reapplied {
+            count: 3,
+            note: "external".to_owned(),
+        })
+        .await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn saves_a_change_received_from_the_watcher() -> eyre::Result<()> {
+    let sandbox = Sandbox::new()?;
+    let key = Counter::key().await?;
+    let mut config = Counter::load().await?;
+    config.modify_and_save(|config| config.count = 1).await?;
+    let mut watcher = Counter::watch().await?;
+
+    let path = sandbox.backend().location(&key)?;
+    std::fs::write(&path, serde_json::to_vec(&Counter { count: 2 })?)?;
+    let received = tokio::time::timeout(Duration::from_secs(10), watcher.recv())
+        .await?
+        .expect("the watcher is running")?;
+    assert_eq!(received.count, 2);
+
+    let mut config = received;
+    config.modify_and_save(|config| config.count = 3).await?;
+    sandbox.assert_contents(&Counter { count: 3 }).await;
+    Ok(())
+}
+
+#[tokio::test]
+async fn config_handle_keeps_external_changes_across_saves() -> eyre::Result<()> {
+    let sandbox = Sandbox::in_memory();
+    let handle = ConfigHandle::<Reapplied>::get().await?;
+    handle.write().count = 1;
+    handle.flush().await?;
+    let external = Reapplied {
+        count: 1,
+        note: "external".to_owned(),
+    };
+    sandbox
+        .backend()
+        .write(&Reapplied::key().await?, &serde_json::to_vec(&external)?)?;
+
+    handle.write().count = 2;
+    handle.flush().await?;
+    assert_eq!(handle.read().note, "external");
+    handle.write().count = 3;
+    handle.flush().await?;
+    sandbox
+        .assert_contents(&Reapplied {
+            count: 3,
+            note: "external".to_owned(),
+        })
+        .await;
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+struct Port {
+    port: u16,
+}
+
+#[async_trait::async_trait]
+impl PersistableState for Port {
+    async fn key() -> eyre::Result<PersistenceKey> {
+        Self::key_blocking()
+    }
+
+    fn key_blocking() -> eyre::Result<PersistenceKey> {
+        Ok(PersistenceKey::new("eye_config_tests", "port.json"))
+    }
+
+    fn recovery_policy() -> RecoveryPolicy<Self> {
+        // Carries the invalid port over, so the recovered config fails validation too.
+        RecoveryPolicy::Custom(|context| {
+            let port = context.value.and_then(|value| value["port"].as_u64());
+            Ok(Port {
+                port: port.unwrap_or_default() as u16,
+            })
+        })
+    }
+
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self.port == 0 {
+            errors.add("/port", "must not be 0");
+        }
+        errors.into_result()
+    }
+}
+
+#[test]
+fn rejects_an_invalid_config_from_custom_recovery() -> eyre::Result<()> {
+    let sandbox = Sandbox::in_memory();
+    sandbox
+        .backend()
+        .write(&Port::key_blocking()?, br#"{"port": 0}"#)?;
+
+    let err = Port::load_blocking().expect_err("the recovered port is 0");
+    let errors = err
+        .downcast_ref::<ValidationErrors>()
+        .expect("the validation errors are returned");
+    assert_eq!(errors.errors[0].path, "/port");
+    Ok(())
+}
+
+#[test]
+fn lists_backups_through_the_backend() -> eyre::Result<()> {
+    let dir = tempfile::tempdir()?;
+    let backends: [Arc<dyn StorageBackend>; 2] = [
+        Arc::new(FilesystemBackend::in_dir(dir.path())),
+        Arc::new(MemoryBackend::new()),
+    ];
+    for backend in backends {
+        let key = PersistenceKey::new("eye_config_tests", "backed_up.json");
+        assert!(backend.list_backups(&key)?.is_empty());
+        backend.write(&key, b"{}")?;
+        let older = backend.backup(&key, BackupRetention::default())?;
+        backend.write(&key, br#"{"count": 1}"#)?;
+        let newer = backend.backup(&key, BackupRetention::default())?;
+
+        let backups = backend.list_backups(&key)?;
+        let paths = backups
+            .iter()
+            .map(|backup| &backup.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [&newer, &older]);
+        assert_eq!(backups[0].len, 12);
+    }
+    Ok(())
+}